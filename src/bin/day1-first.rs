@@ -1,22 +1,12 @@
 use aoc_2024::day1;
-use std::fs;
+use std::fs::File;
+use std::io::BufReader;
 
 fn main() {
-    let input = fs::read_to_string("data/day1/input").unwrap();
-    let (mut left, mut right) = day1::parse_input(&input);
-    let total_distance = total_distance(&mut left, &mut right);
+    let input = File::open("data/day1/input").unwrap();
+    let lists: day1::LocationLists = day1::parse_pairs(BufReader::new(input))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let total_distance = lists.total_distance();
     println!("{}", total_distance);
 }
-
-fn total_distance(
-    left: &mut Vec<day1::LocationID>,
-    right: &mut Vec<day1::LocationID>,
-) -> day1::LocationID {
-    left.sort();
-    right.sort();
-
-    left.iter()
-        .zip(right.iter())
-        .map(|(l, r)| (l - r).abs())
-        .sum()
-}
@@ -6,8 +6,9 @@ fn main() {
     let input = fs::read_to_string("data/day13/input").unwrap();
     let machines = day13::parse_input(&input);
     let total_tokens: usize = machines
-        .iter()
-        .filter_map(|machine| machine.required_tokens())
+        .into_iter()
+        .map(|machine| machine.with_press_limit(100))
+        .filter_map(|machine| machine.required_tokens(0))
         .sum();
     println!("{}", total_tokens);
 }
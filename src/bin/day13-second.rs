@@ -1,13 +1,21 @@
-use std::fs;
+use std::{env, fs};
 
 use aoc_2024::day13;
 
+const DEFAULT_PRIZE_OFFSET: usize = 10000000000000;
+
 fn main() {
     let input = fs::read_to_string("data/day13/input").unwrap();
     let machines = day13::parse_input(&input);
+
+    // The offset can be passed as the first argument
+    let prize_offset = env::args()
+        .nth(1)
+        .map(|offset| offset.parse().unwrap())
+        .unwrap_or(DEFAULT_PRIZE_OFFSET);
     let total_tokens: usize = machines
         .iter()
-        .filter_map(|machine| machine.required_tokens_adjusted())
+        .filter_map(|machine| machine.required_tokens(prize_offset))
         .sum();
     println!("{}", total_tokens);
 }
@@ -1,6 +1,7 @@
 use std::fs;
 
 use aoc_2024::day15;
+use aoc_2024::obstacles::NextObstacle;
 use aoc_2024::spatial::Direction;
 
 fn main() {
@@ -44,14 +45,18 @@ impl WideWarehouse {
 
     fn apply_horizontal_move(&mut self, move_: day15::Move) {
         let delta = day15::Position::from(move_);
+        let wall = self.0.walls.next_obstacle(self.0.robot, move_);
         let mut current = self.0.robot + delta;
 
-        while self.0.is_valid(current) && self.get_box_at(current).is_some() {
+        while Some(current) != wall
+            && self.0.is_valid(current)
+            && self.get_box_at(current).is_some()
+        {
             // We can fast-forward because boxes are wide
             current = current + delta * 2;
         }
 
-        if self.is_blocking(current) {
+        if !self.0.is_valid(current) || Some(current) == wall {
             // We bumped into a wall, so we don't move
             return;
         }
@@ -1,5 +1,5 @@
 use aoc_2024::day5;
-use std::fs;
+use std::{env, fs};
 
 fn main() {
     let input = fs::read_to_string("data/day5/input").unwrap();
@@ -10,5 +10,31 @@ fn main() {
         .map(day5::PageUpdate::middle_page)
         .sum();
 
+    // Pass --explain to see why each invalid update is invalid
+    if env::args().any(|arg| arg == "--explain") {
+        let graph = day5::RuleGraph::from(&rules);
+        for (idx, update) in updates.iter().enumerate() {
+            explain(idx, &update.validate(&graph));
+        }
+    }
+
     println!("{}", checksum);
 }
+
+fn explain(idx: usize, report: &day5::ValidationReport) {
+    if report.is_valid() {
+        return;
+    }
+
+    println!("Update {} is invalid:", idx + 1);
+    for violation in report.violations.iter() {
+        println!(
+            "  {} is after {}, breaking {:?}",
+            violation.page, violation.previous_page, violation.rule
+        );
+    }
+    match &report.pages_to_move {
+        Ok(pages) => println!("  Fixed by moving pages {:?}", pages),
+        Err(cycle) => println!("  Cannot be fixed, rules go round {}", cycle),
+    }
+}
@@ -3,7 +3,7 @@ use std::fs;
 
 fn main() {
     let input = fs::read_to_string("data/day6/input").unwrap();
-    let (grid, guard) = day6::parse_input(&input);
-    let unique_positions = grid.unique_positions(&guard);
+    let (grid, guards) = day6::parse_input(&input);
+    let unique_positions = grid.unique_positions(&guards).unwrap();
     println!("{}", unique_positions.len());
 }
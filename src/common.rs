@@ -1,13 +1,46 @@
 use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::ops::RangeInclusive;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Cycle {
     pub mu: usize,     // the length of the prefix
     pub lambda: usize, // the actual cycle length
 }
 
+impl Cycle {
+    /// Maps an index in an eventually periodic sequence to the index of an
+    /// equal element within the first mu + lambda ones.
+    pub fn reduce_index(&self, n: u64) -> usize {
+        let mu = self.mu as u64;
+
+        if n < mu {
+            return n as usize;
+        }
+
+        (mu + (n - mu) % self.lambda as u64) as usize
+    }
+}
+
+/// The sequence ended before repeating itself, after yielding length elements
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Finite {
+    pub length: usize,
+}
+
 pub trait CycleDetection {
+    type Item;
+
+    /// Floyd's tortoise and hare. Returns None if the sequence is finite.
     fn detect_cycle(&self) -> Option<Cycle>;
+
+    /// Brent's algorithm, which evaluates fewer elements than Floyd's.
+    fn detect_cycle_brent(&self) -> Result<Cycle, Finite>;
+
+    /// Element at index n of an eventually periodic sequence, without
+    /// evaluating more than mu + lambda elements after detecting the cycle.
+    fn nth_periodic(&self, n: u64) -> Option<Self::Item>;
 }
 
 impl<T> CycleDetection for T
@@ -15,6 +48,8 @@ where
     T: IntoIterator + Clone,
     T::Item: Eq,
 {
+    type Item = T::Item;
+
     fn detect_cycle(&self) -> Option<Cycle> {
         let mut tortoise_iter = self.clone().into_iter();
         let mut hare_iter = self.clone().into_iter().skip(1);
@@ -45,6 +80,103 @@ where
 
         Some(Cycle { mu, lambda })
     }
+
+    fn detect_cycle_brent(&self) -> Result<Cycle, Finite> {
+        let finite = |length| Finite { length };
+        let mut hare_iter = self.clone().into_iter();
+        let mut tortoise = hare_iter.next().ok_or(finite(0))?;
+        let mut hare = hare_iter.next().ok_or(finite(1))?;
+        let mut read = 2;
+        let mut power = 1;
+        let mut lambda = 1;
+
+        // Find the cycle length by looking for the tortoise in increasingly
+        // long windows ahead of it
+        while tortoise != hare {
+            if power == lambda {
+                tortoise = hare;
+                power *= 2;
+                lambda = 0;
+            }
+
+            hare = hare_iter.next().ok_or(finite(read))?;
+            read += 1;
+            lambda += 1;
+        }
+
+        // Then the start of the cycle, keeping the hare lambda elements ahead
+        let mut tortoise_iter = self.clone().into_iter();
+        let mut hare_iter = self.clone().into_iter().skip(lambda);
+        let mut mu = 0;
+        tortoise = tortoise_iter.next().ok_or(finite(0))?;
+        hare = hare_iter.next().ok_or(finite(lambda))?;
+        while tortoise != hare {
+            tortoise = tortoise_iter.next().ok_or(finite(mu))?;
+            hare = hare_iter.next().ok_or(finite(mu + lambda))?;
+            mu += 1;
+        }
+
+        Ok(Cycle { mu, lambda })
+    }
+
+    fn nth_periodic(&self, n: u64) -> Option<T::Item> {
+        let idx = match self.detect_cycle_brent() {
+            Ok(cycle) => cycle.reduce_index(n),
+            Err(Finite { length }) if n < length as u64 => n as usize,
+            Err(_) => return None,
+        };
+
+        self.clone().into_iter().nth(idx)
+    }
+}
+
+/// Cycle detection for sequences that are expensive or impossible to replay.
+/// It consumes the iterator once, remembering the index at which each element
+/// was first seen.
+pub trait HashedCycleDetection: Iterator {
+    fn detect_cycle_hashed(self) -> Result<PeriodicHistory<Self::Item>, Finite>;
+}
+
+impl<I> HashedCycleDetection for I
+where
+    I: Iterator,
+    I::Item: Clone + Eq + Hash,
+{
+    fn detect_cycle_hashed(self) -> Result<PeriodicHistory<Self::Item>, Finite> {
+        let mut first_seen = HashMap::new();
+        let mut elements = vec![];
+
+        for (idx, element) in self.enumerate() {
+            if let Some(&mu) = first_seen.get(&element) {
+                let cycle = Cycle {
+                    mu,
+                    lambda: idx - mu,
+                };
+                return Ok(PeriodicHistory { cycle, elements });
+            }
+
+            first_seen.insert(element.clone(), idx);
+            elements.push(element);
+        }
+
+        Err(Finite {
+            length: elements.len(),
+        })
+    }
+}
+
+/// The first mu + lambda elements of an eventually periodic sequence, which
+/// determine every other element.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PeriodicHistory<Item> {
+    pub cycle: Cycle,
+    elements: Vec<Item>,
+}
+
+impl<Item> PeriodicHistory<Item> {
+    pub fn nth(&self, n: u64) -> &Item {
+        &self.elements[self.cycle.reduce_index(n)]
+    }
 }
 
 pub fn pairs<T: Clone>(list: &[T]) -> Vec<(T, T)> {
@@ -112,6 +244,260 @@ pub enum Match {
     Nothing,
 }
 
+/// Where a monotone predicate (false for a prefix, true for the rest) flips.
+/// Either side is None when the predicate never takes that value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Partition<T> {
+    pub last_false: Option<T>,
+    pub first_true: Option<T>,
+}
+
+/// Integers that can be searched over without overflowing at the edges
+pub trait SearchInteger: Copy + Ord {
+    const ONE: Self;
+    const MAX: Self;
+
+    /// Rounds down, and does not overflow even for extreme values
+    fn midpoint(self, other: Self) -> Self;
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn successor(self) -> Self;
+    fn predecessor(self) -> Self;
+}
+
+macro_rules! impl_search_integer {
+    ($($t:ty),*) => {
+        $(
+            impl SearchInteger for $t {
+                const ONE: Self = 1;
+                const MAX: Self = <$t>::MAX;
+
+                fn midpoint(self, other: Self) -> Self {
+                    // Average without the intermediate sum
+                    (self & other) + ((self ^ other) >> 1)
+                }
+
+                fn checked_add(self, other: Self) -> Option<Self> {
+                    <$t>::checked_add(self, other)
+                }
+
+                fn successor(self) -> Self {
+                    self + 1
+                }
+
+                fn predecessor(self) -> Self {
+                    self - 1
+                }
+            }
+        )*
+    };
+}
+
+impl_search_integer!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+/// Finds where a monotone predicate flips from false to true in the given range.
+pub fn partition_point<T, P>(range: RangeInclusive<T>, mut predicate: P) -> Partition<T>
+where
+    T: SearchInteger,
+    P: FnMut(T) -> bool,
+{
+    let (start, end) = range.into_inner();
+
+    if start > end {
+        return Partition {
+            last_false: None,
+            first_true: None,
+        };
+    }
+
+    if !predicate(end) {
+        return Partition {
+            last_false: Some(end),
+            first_true: None,
+        };
+    }
+
+    // The first true is always within [low, high]
+    let mut low = start;
+    let mut high = end;
+    while low < high {
+        let mid = low.midpoint(high);
+        if predicate(mid) {
+            high = mid;
+        } else {
+            low = mid.successor();
+        }
+    }
+
+    Partition {
+        last_false: Some(low).filter(|&low| low > start).map(T::predecessor),
+        first_true: Some(low),
+    }
+}
+
+/// Same as partition_point for a range with no known end: probes
+/// exponentially growing distances from start until the predicate is true,
+/// and then searches the last gap.
+pub fn partition_point_unbounded<T, P>(start: T, mut predicate: P) -> Partition<T>
+where
+    T: SearchInteger,
+    P: FnMut(T) -> bool,
+{
+    let mut low = start;
+    let mut step = T::ONE;
+
+    loop {
+        let (probe, overflowed) = match low.checked_add(step) {
+            Some(probe) => (probe, false),
+            None => (T::MAX, true),
+        };
+
+        if predicate(probe) {
+            break partition_point(low..=probe, predicate);
+        } else if overflowed {
+            break Partition {
+                last_false: Some(T::MAX),
+                first_true: None,
+            };
+        }
+
+        low = probe;
+        step = step.checked_add(step).unwrap_or(T::MAX);
+    }
+}
+
+/// Bisection for a monotone predicate over the reals. Stops when the gap
+/// between the last false and first true values is at most tolerance, or
+/// when f64 runs out of precision in between.
+pub fn bisect<P: FnMut(f64) -> bool>(
+    low: f64,
+    high: f64,
+    tolerance: f64,
+    mut predicate: P,
+) -> Partition<f64> {
+    if predicate(low) {
+        return Partition {
+            last_false: None,
+            first_true: Some(low),
+        };
+    } else if !predicate(high) {
+        return Partition {
+            last_false: Some(high),
+            first_true: None,
+        };
+    }
+
+    let (mut last_false, mut first_true) = (low, high);
+    while first_true - last_false > tolerance {
+        let mid = last_false + (first_true - last_false) / 2.0;
+        if mid <= last_false || mid >= first_true {
+            break;
+        }
+
+        if predicate(mid) {
+            first_true = mid;
+        } else {
+            last_false = mid;
+        }
+    }
+
+    Partition {
+        last_false: Some(last_false),
+        first_true: Some(first_true),
+    }
+}
+
+/// Aho-Corasick automaton: a trie of the words with failure links, so that
+/// scanning a sequence one symbol at a time finds every word ending at each
+/// symbol without backtracking. Scans start from state 0, words are numbered
+/// in the order given, and empty ones never match.
+pub struct Automaton<S> {
+    children: Vec<HashMap<S, usize>>,
+    failures: Vec<usize>,
+    /// Words ending at each node, including those reached through failures
+    outputs: Vec<Vec<usize>>,
+}
+
+impl<S: Copy + Eq + Hash> Automaton<S> {
+    pub fn new<I, W>(words: I) -> Self
+    where
+        I: IntoIterator<Item = W>,
+        W: IntoIterator<Item = S>,
+    {
+        let mut automaton = Self {
+            children: vec![HashMap::new()],
+            failures: vec![0],
+            outputs: vec![vec![]],
+        };
+
+        for (idx, word) in words.into_iter().enumerate() {
+            let mut node = 0;
+            for symbol in word {
+                node = match automaton.children[node].get(&symbol) {
+                    Some(&child) => child,
+                    None => {
+                        automaton.children.push(HashMap::new());
+                        automaton.failures.push(0);
+                        automaton.outputs.push(vec![]);
+                        let child = automaton.children.len() - 1;
+                        automaton.children[node].insert(symbol, child);
+                        child
+                    }
+                };
+            }
+            if node != 0 {
+                automaton.outputs[node].push(idx);
+            }
+        }
+
+        // Breadth first, so the failure of a node's parent is already known
+        let mut pending: VecDeque<usize> = automaton.children[0].values().copied().collect();
+        while let Some(node) = pending.pop_front() {
+            let children: Vec<(S, usize)> = automaton.children[node]
+                .iter()
+                .map(|(&symbol, &child)| (symbol, child))
+                .collect();
+
+            for (symbol, child) in children {
+                let failure = if node == 0 {
+                    0
+                } else {
+                    automaton.next_state(automaton.failures[node], symbol)
+                };
+                automaton.failures[child] = failure;
+                let inherited = automaton.outputs[failure].clone();
+                automaton.outputs[child].extend(inherited);
+                pending.push_back(child);
+            }
+        }
+
+        automaton
+    }
+
+    pub fn next_state(&self, mut state: usize, symbol: S) -> usize {
+        loop {
+            if let Some(&child) = self.children[state].get(&symbol) {
+                return child;
+            }
+            if state == 0 {
+                return 0;
+            }
+            state = self.failures[state];
+        }
+    }
+
+    /// The words ending at the last symbol read, longest first: those that
+    /// spell the whole path from the start come before the inherited ones
+    pub fn outputs(&self, state: usize) -> &[usize] {
+        &self.outputs[state]
+    }
+
+    /// The trie node after the given one, without following failures, so
+    /// the path from the start stays a prefix of some word
+    pub fn child(&self, node: usize, symbol: S) -> Option<usize> {
+        self.children[node].get(&symbol).copied()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,6 +514,47 @@ mod tests {
         assert_eq!(cycle.lambda, 6);
     }
 
+    #[test]
+    fn test_detect_cycle_brent() {
+        let cases = [
+            ((0..5).chain((5..11).cycle()), Cycle { mu: 5, lambda: 6 }),
+            ((0..0).chain((5..11).cycle()), Cycle { mu: 0, lambda: 6 }),
+            ((0..1).chain((5..6).cycle()), Cycle { mu: 1, lambda: 1 }),
+        ];
+
+        for (iterator, expected) in cases {
+            assert_eq!(Ok(expected), iterator.detect_cycle_brent());
+        }
+
+        assert_eq!(Err(Finite { length: 7 }), (0..7).detect_cycle_brent());
+        assert_eq!(Err(Finite { length: 0 }), (0..0).detect_cycle_brent());
+    }
+
+    #[test]
+    fn test_detect_cycle_hashed() {
+        // x -> x^2 + 1 mod 255 starting at 3 is eventually periodic
+        let sequence = std::iter::successors(Some(3u64), |x| Some((x * x + 1) % 255));
+        let history = sequence.clone().detect_cycle_hashed().unwrap();
+
+        assert_eq!(Ok(history.cycle), sequence.detect_cycle_brent());
+        assert_eq!(Err(Finite { length: 3 }), (0..3).detect_cycle_hashed());
+    }
+
+    #[test]
+    fn test_nth_periodic() {
+        let iterator = (0..5).chain((5..11).cycle());
+        let history = iterator.clone().detect_cycle_hashed().unwrap();
+
+        for n in [0, 4, 5, 10, 11, 1_000_000_000_000_000_000] {
+            let expected = if n < 5 { n } else { 5 + (n - 5) % 6 };
+            assert_eq!(Some(expected), iterator.nth_periodic(n));
+            assert_eq!(expected, *history.nth(n));
+        }
+
+        assert_eq!(Some(3), (0..7).nth_periodic(3));
+        assert_eq!(None, (0..7).nth_periodic(7));
+    }
+
     #[test]
     fn test_pairs() {
         let list = vec![1, 2, 3, 4];
@@ -165,4 +592,125 @@ mod tests {
             assert_eq!(expected, binary_search(0, xs.len() - 1, |i| xs[i].cmp(&0)));
         }
     }
+
+    #[test]
+    fn test_partition_point() {
+        let xs = [-5, -3, -2, -1, 2, 4, 6];
+        let partition = |range| partition_point(range, |i: usize| xs[i] > 0);
+
+        assert_eq!(
+            Partition {
+                last_false: Some(3),
+                first_true: Some(4)
+            },
+            partition(0..=6)
+        );
+        assert_eq!(
+            Partition {
+                last_false: None,
+                first_true: Some(4)
+            },
+            partition(4..=6)
+        );
+        assert_eq!(
+            Partition {
+                last_false: Some(3),
+                first_true: None
+            },
+            partition(0..=3)
+        );
+        // An empty range, built from its bounds as a literal one is a lint
+        let (start, end) = (4, 3);
+        assert_eq!(
+            Partition {
+                last_false: None,
+                first_true: None
+            },
+            partition(start..=end)
+        );
+    }
+
+    #[test]
+    fn test_partition_point_extremes() {
+        assert_eq!(
+            Partition {
+                last_false: Some(-1),
+                first_true: Some(0)
+            },
+            partition_point(i64::MIN..=i64::MAX, |x| x >= 0)
+        );
+        assert_eq!(
+            Partition {
+                last_false: Some(u128::MAX - 1),
+                first_true: Some(u128::MAX)
+            },
+            partition_point(0..=u128::MAX, |x| x == u128::MAX)
+        );
+        // Smallest x with x^2 >= 10^18
+        assert_eq!(
+            Some(1_000_000_000),
+            partition_point(0u64..=u32::MAX as u64, |x| x * x
+                >= 1_000_000_000_000_000_000)
+            .first_true
+        );
+    }
+
+    #[test]
+    fn test_partition_point_unbounded() {
+        assert_eq!(
+            Partition {
+                last_false: Some(1_234_566),
+                first_true: Some(1_234_567)
+            },
+            partition_point_unbounded(0u64, |x| x >= 1_234_567)
+        );
+        assert_eq!(
+            Partition {
+                last_false: None,
+                first_true: Some(-3)
+            },
+            partition_point_unbounded(-3i32, |_| true)
+        );
+        assert_eq!(
+            Partition {
+                last_false: Some(u8::MAX),
+                first_true: None
+            },
+            partition_point_unbounded(0u8, |_| false)
+        );
+    }
+
+    #[test]
+    fn test_bisect() {
+        let sqrt_2 = bisect(0.0, 2.0, 1e-9, |x| x * x >= 2.0);
+        let (last_false, first_true) = (sqrt_2.last_false.unwrap(), sqrt_2.first_true.unwrap());
+
+        assert!(last_false * last_false < 2.0);
+        assert!(first_true * first_true >= 2.0);
+        assert!(first_true - last_false <= 1e-9);
+        assert_eq!(None, bisect(0.0, 1.0, 1e-9, |x| x > 2.0).first_true);
+    }
+
+    #[test]
+    fn test_automaton() {
+        let words = ["he", "she", "his", "hers", "", "he"];
+        let automaton = Automaton::new(words.iter().map(|word| word.chars()));
+
+        let mut state = 0;
+        let mut found = vec![];
+        for (idx, symbol) in "ushers".chars().enumerate() {
+            state = automaton.next_state(state, symbol);
+            for &word in automaton.outputs(state) {
+                found.push((idx + 1 - words[word].len(), words[word], word));
+            }
+        }
+        assert_eq!(
+            vec![(1, "she", 1), (2, "he", 0), (2, "he", 5), (2, "hers", 3)],
+            found
+        );
+
+        let h = automaton.child(0, 'h').unwrap();
+        assert!(automaton.child(h, 'i').is_some());
+        assert_eq!(None, automaton.child(h, 's'));
+    }
 }
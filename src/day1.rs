@@ -1,14 +1,274 @@
+use std::cmp::{min, Reverse};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufRead};
+use std::num::NonZeroU32;
+
 pub type LocationID = i32;
 
-pub fn parse_input(input: &str) -> (Vec<LocationID>, Vec<LocationID>) {
-    input
-        .lines()
-        .map(|line| {
-            let parts = line.split_once("   ").unwrap();
-            (
-                parts.0.parse::<i32>().unwrap(),
-                parts.1.parse::<i32>().unwrap(),
-            )
+pub fn parse_input(input: &str) -> Result<(Vec<LocationID>, Vec<LocationID>), ParseError> {
+    parse_pairs(input.as_bytes()).collect()
+}
+
+/// Lazily parses each line into a pair of IDs, so large inputs can be fed to
+/// LocationLists without holding both columns in memory. A line that cannot
+/// be read or parsed yields an error and the following lines are still read.
+pub fn parse_pairs<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<(LocationID, LocationID), ParseError>> {
+    reader.lines().enumerate().map(|(idx, line)| {
+        let line = line.map_err(ParseError::Io)?;
+        parse_line(&line).ok_or_else(|| ParseError::InvalidLine {
+            number: idx + 1,
+            line,
+        })
+    })
+}
+
+fn parse_line(line: &str) -> Option<(LocationID, LocationID)> {
+    let mut parts = line.split_whitespace();
+    let left = parts.next()?.parse().ok()?;
+    let right = parts.next()?.parse().ok()?;
+
+    parts.next().is_none().then_some((left, right))
+}
+
+/// Why the location lists could not be read
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    /// A line that is not two IDs, numbered from 1
+    InvalidLine {
+        number: usize,
+        line: String,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(error) => write!(f, "{}", error),
+            ParseError::InvalidLine { number, line } => {
+                write!(f, "line {} is not two location IDs: {:?}", number, line)
+            }
+        }
+    }
+}
+
+/// Both location lists, kept as how many times each ID shows up on each side.
+/// Memory depends on the number of distinct IDs rather than on the length of
+/// the lists, and sums are done in i64 so they do not overflow.
+#[derive(Clone, Debug, Default)]
+pub struct LocationLists {
+    left: BTreeMap<LocationID, u64>,
+    right: BTreeMap<LocationID, u64>,
+}
+
+impl LocationLists {
+    pub fn insert(&mut self, left: LocationID, right: LocationID) {
+        *self.left.entry(left).or_insert(0) += 1;
+        *self.right.entry(right).or_insert(0) += 1;
+    }
+
+    /// Sum of the distances between the smallest IDs of each list, then the
+    /// second smallest ones, and so on.
+    pub fn total_distance(&self) -> i64 {
+        self.paired_distances()
+            .into_iter()
+            .map(|(distance, count)| distance * count as i64)
+            .sum()
+    }
+
+    /// Sum of each ID on the left times how many times it is on the right
+    pub fn similarity_score(&self) -> i64 {
+        self.similarities().map(|(_, score)| score).sum()
+    }
+
+    /// The median of the distances added up by total_distance, halfway
+    /// between the two middle ones for an even number of pairs.
+    pub fn median_gap(&self) -> Option<f64> {
+        let histogram = self.distance_histogram(NonZeroU32::MIN);
+        let pairs: u64 = histogram.values().sum();
+        if pairs == 0 {
+            return None;
+        }
+
+        // Distances at the (0-based) positions below, in increasing order
+        let nth = |position: u64| {
+            let mut seen = 0;
+            histogram
+                .iter()
+                .find(|(_, &count)| {
+                    seen += count;
+                    seen > position
+                })
+                .map(|(&distance, _)| distance)
+                .unwrap()
+        };
+
+        Some((nth((pairs - 1) / 2) + nth(pairs / 2)) as f64 / 2.0)
+    }
+
+    /// How many pairs have a distance in each bucket of the given width,
+    /// with buckets identified by their smallest distance.
+    pub fn distance_histogram(&self, bucket_width: NonZeroU32) -> BTreeMap<i64, u64> {
+        let bucket_width = i64::from(bucket_width.get());
+        let mut histogram = BTreeMap::new();
+
+        for (distance, count) in self.paired_distances() {
+            let bucket = distance - distance % bucket_width;
+            *histogram.entry(bucket).or_insert(0) += count;
+        }
+
+        histogram
+    }
+
+    /// The k IDs that add the most to the similarity score, with how much
+    /// they add, from the most similar one down.
+    pub fn most_similar(&self, k: usize) -> Vec<(LocationID, i64)> {
+        let mut similarities: Vec<_> = self
+            .similarities()
+            .filter(|&(_, score)| score != 0)
+            .collect();
+        similarities.sort_by_key(|&(id, score)| (Reverse(score), id));
+        similarities.truncate(k);
+
+        similarities
+    }
+
+    fn similarities(&self) -> impl Iterator<Item = (LocationID, i64)> + '_ {
+        self.left.iter().map(|(&id, &left_count)| {
+            let right_count = self.right.get(&id).copied().unwrap_or(0);
+            (id, id as i64 * (left_count * right_count) as i64)
         })
-        .unzip()
+    }
+
+    /// Distances between the sorted lists as (distance, number of pairs),
+    /// walking both lists one run of equal IDs at a time.
+    fn paired_distances(&self) -> Vec<(i64, u64)> {
+        let mut left = self.left.iter().map(|(&id, &count)| (id, count));
+        let mut right = self.right.iter().map(|(&id, &count)| (id, count));
+        let mut current = (left.next(), right.next());
+        let mut distances = vec![];
+
+        while let (Some((left_id, left_count)), Some((right_id, right_count))) = current {
+            let pairs = min(left_count, right_count);
+            distances.push(((left_id as i64 - right_id as i64).abs(), pairs));
+
+            current = (
+                Some((left_id, left_count - pairs))
+                    .filter(|&(_, count)| count > 0)
+                    .or_else(|| left.next()),
+                Some((right_id, right_count - pairs))
+                    .filter(|&(_, count)| count > 0)
+                    .or_else(|| right.next()),
+            );
+        }
+
+        distances
+    }
+}
+
+impl FromIterator<(LocationID, LocationID)> for LocationLists {
+    fn from_iter<I: IntoIterator<Item = (LocationID, LocationID)>>(pairs: I) -> Self {
+        let mut lists = Self::default();
+
+        for (left, right) in pairs {
+            lists.insert(left, right);
+        }
+
+        lists
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_total_distance_and_similarity() {
+        let input = fs::read_to_string("data/day1/test_input").unwrap();
+        let lists: LocationLists = parse_pairs(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(11, lists.total_distance());
+        assert_eq!(31, lists.similarity_score());
+    }
+
+    #[test]
+    fn test_statistics() {
+        let input = fs::read_to_string("data/day1/test_input").unwrap();
+        let lists: LocationLists = parse_pairs(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        // Distances are 2, 1, 0, 1, 2, 5
+        assert_eq!(Some(1.5), lists.median_gap());
+        assert_eq!(
+            BTreeMap::from([(0, 3), (2, 2), (4, 1)]),
+            lists.distance_histogram(NonZeroU32::new(2).unwrap())
+        );
+        assert_eq!(vec![(3, 27), (4, 4)], lists.most_similar(5));
+        assert_eq!(vec![(3, 27)], lists.most_similar(1));
+        assert_eq!(None, LocationLists::default().median_gap());
+    }
+
+    #[test]
+    fn test_distance_histogram() {
+        let input = fs::read_to_string("data/day1/test_input").unwrap();
+        let (left, right) = parse_input(&input).unwrap();
+        let lists: LocationLists = left.into_iter().zip(right).collect();
+
+        assert_eq!(
+            BTreeMap::from([(0, 1), (1, 2), (2, 2), (5, 1)]),
+            lists.distance_histogram(NonZeroU32::MIN)
+        );
+        // A single bucket past every distance
+        assert_eq!(
+            BTreeMap::from([(0, 6)]),
+            lists.distance_histogram(NonZeroU32::MAX)
+        );
+    }
+
+    #[test]
+    fn test_invalid_lines() {
+        let input = "3   4\n4 x\n\n1   2   3\n9   3\n";
+        let pairs: Vec<_> = parse_pairs(input.as_bytes()).collect();
+
+        assert_eq!(5, pairs.len());
+        assert_eq!(Some(&(3, 4)), pairs[0].as_ref().ok());
+        assert!(matches!(
+            pairs[1],
+            Err(ParseError::InvalidLine { number: 2, .. })
+        ));
+        assert!(matches!(
+            pairs[2],
+            Err(ParseError::InvalidLine { number: 3, .. })
+        ));
+        assert!(matches!(
+            pairs[3],
+            Err(ParseError::InvalidLine { number: 4, .. })
+        ));
+        assert_eq!(Some(&(9, 3)), pairs[4].as_ref().ok());
+        assert_eq!(
+            "line 2 is not two location IDs: \"4 x\"",
+            pairs[1].as_ref().unwrap_err().to_string()
+        );
+        assert!(parse_input(input).is_err());
+    }
+
+    #[test]
+    fn test_no_overflow() {
+        let lists: LocationLists = (0..1000)
+            .map(|_| (LocationID::MAX, LocationID::MIN))
+            .collect();
+
+        assert_eq!(
+            1000 * (LocationID::MAX as i64 - LocationID::MIN as i64),
+            lists.total_distance()
+        );
+        assert_eq!(0, lists.similarity_score());
+    }
 }
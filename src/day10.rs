@@ -1,99 +1,319 @@
 use crate::spatial::Point2D;
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
 type Coordinate = i16;
 type Position = Point2D<Coordinate>;
 type Level = u8;
 
+/// A topographic map. Heights are single base 36 digits, so besides 0 to 9
+/// the letters a to z stand for heights 10 to 35. Any other tile, such as a
+/// '.', is impassable.
 pub struct Map {
     rows: usize,
     columns: usize,
     levels: HashMap<Position, Level>,
+    trailhead_level: Level,
+    summit_level: Level,
+}
+
+/// For every cell on the way from a trailhead level to a summit level, how
+/// many distinct trails lead from it to a summit and which summits those are.
+pub struct Trails {
+    ratings: HashMap<Position, usize>,
+    reachable: HashMap<Position, SummitSet>,
+    summits: Vec<Position>,
+}
+
+/// A bitset over the indices of Trails::summits
+type SummitSet = Vec<u64>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TrailheadSummary {
+    pub position: Position,
+    /// Number of summits reachable from the trailhead
+    pub score: usize,
+    /// Number of distinct trails from the trailhead to any summit
+    pub rating: usize,
 }
 
 impl Map {
+    /// Changes the levels trails start and end at, 0 and 9 by default
+    pub fn with_levels(self, trailhead_level: Level, summit_level: Level) -> Self {
+        Self {
+            trailhead_level,
+            summit_level,
+            ..self
+        }
+    }
+
     pub fn total_score(&self) -> usize {
-        self.levels
+        self.trailheads_summary()
             .iter()
-            .map(|(start, _)| self.trailhead_score(start))
+            .map(|trailhead| trailhead.score)
             .sum()
     }
 
     pub fn total_rating(&self) -> usize {
-        self.levels
+        self.trailheads_summary()
             .iter()
-            .map(|(start, _)| self.trailhead_rating(start))
+            .map(|trailhead| trailhead.rating)
             .sum()
     }
 
-    fn trailhead_score(&self, start: &Position) -> usize {
-        if self.levels[start] != 0 {
-            return 0;
+    /// Score and rating of every trailhead, top to bottom and left to right
+    pub fn trailheads_summary(&self) -> Vec<TrailheadSummary> {
+        let trails = self.trails();
+
+        self.cells_at(self.trailhead_level)
+            .into_iter()
+            .map(|position| TrailheadSummary {
+                position,
+                score: trails.score(&position),
+                rating: trails.rating(&position),
+            })
+            .collect()
+    }
+
+    /// Counts trails for the whole map at once, one level at a time from the
+    /// summits down. Each cell adds up the trails and merges the summits of
+    /// its neighbours one level above, which are already known by then.
+    pub fn trails(&self) -> Trails {
+        let summits = self.cells_at(self.summit_level);
+        let words = summits.len().div_ceil(64);
+        let mut ratings = HashMap::new();
+        let mut reachable: HashMap<Position, SummitSet> = HashMap::new();
+
+        for (idx, summit) in summits.iter().enumerate() {
+            let mut summit_set = vec![0; words];
+            summit_set[idx / 64] |= 1 << (idx % 64);
+            reachable.insert(*summit, summit_set);
+            ratings.insert(*summit, 1);
         }
 
-        let mut heads = vec![(0, *start)];
-        let mut visited: HashSet<Position> = HashSet::new();
-        let mut nines = 0;
+        for level in (self.trailhead_level..self.summit_level).rev() {
+            for position in self.cells_at(level) {
+                let mut rating = 0;
+                let mut summit_set = vec![0; words];
 
-        while let Some((level, position)) = heads.pop() {
-            if level == 9 {
-                nines += 1;
-                continue;
+                for neighbour in self.uphill(&position) {
+                    rating += ratings[&neighbour];
+                    for (word, other) in summit_set.iter_mut().zip(reachable[&neighbour].iter()) {
+                        *word |= other;
+                    }
+                }
+
+                ratings.insert(position, rating);
+                reachable.insert(position, summit_set);
             }
+        }
 
-            let to_visit: Vec<_> = position
-                .neighbours()
-                .into_iter()
-                .filter(|neighbour| {
-                    self.is_valid(neighbour)
-                        && self.levels[neighbour] == level + 1
-                        && !visited.contains(neighbour)
-                })
-                .collect();
+        Trails {
+            ratings,
+            reachable,
+            summits,
+        }
+    }
 
-            heads.extend(
-                to_visit
-                    .iter()
-                    .cloned()
-                    .map(|neighbour| (level + 1, neighbour)),
-            );
-            visited.extend(to_visit);
+    /// Summits that can be reached from the given cell, top to bottom and
+    /// left to right. Only climbs from that cell, so for many cells it is
+    /// better to build the trails once and ask them instead.
+    pub fn reachable_summits(&self, trailhead: &Position) -> Vec<Position> {
+        if !self.levels.contains_key(trailhead) {
+            return vec![];
         }
 
-        nines
+        let mut pending = vec![*trailhead];
+        let mut visited = HashSet::from([*trailhead]);
+        let mut summits = vec![];
+
+        while let Some(position) = pending.pop() {
+            if self.levels[&position] == self.summit_level {
+                summits.push(position);
+                continue;
+            }
+
+            for neighbour in self.uphill(&position) {
+                if visited.insert(neighbour) {
+                    pending.push(neighbour);
+                }
+            }
+        }
+        summits.sort_by_key(|position| (position.y, position.x));
+
+        summits
     }
 
-    fn trailhead_rating(&self, start: &Position) -> usize {
-        if self.levels[start] != 0 {
-            return 0;
+    /// The reverse of reachable_summits: trailheads with at least one trail
+    /// ending at the given summit, top to bottom and left to right.
+    pub fn trailheads_feeding(&self, summit: &Position) -> Vec<Position> {
+        if self.levels.get(summit) != Some(&self.summit_level) {
+            return vec![];
         }
 
-        let mut heads = vec![(0, *start)];
-        let mut nines = 0;
+        let mut pending = vec![*summit];
+        let mut visited = HashSet::from([*summit]);
+        let mut trailheads = vec![];
 
-        while let Some((level, position)) = heads.pop() {
-            if level == 9 {
-                nines += 1;
+        while let Some(position) = pending.pop() {
+            if self.levels[&position] == self.trailhead_level {
+                trailheads.push(position);
                 continue;
             }
 
-            let to_visit: Vec<_> = position
+            for neighbour in self.downhill(&position) {
+                if visited.insert(neighbour) {
+                    pending.push(neighbour);
+                }
+            }
+        }
+        trailheads.sort_by_key(|position| (position.y, position.x));
+
+        trailheads
+    }
+
+    /// Cells on at least one trail from a trailhead to a summit
+    pub fn trail_network(&self) -> HashSet<Position> {
+        let trails = self.trails();
+        let mut network: HashSet<Position> = self
+            .cells_at(self.trailhead_level)
+            .into_iter()
+            .filter(|position| trails.rating(position) > 0)
+            .collect();
+
+        // A cell is on a trail if it is reached from one below that is on a
+        // trail, and some trail goes on from it up to a summit
+        for level in self.trailhead_level + 1..=self.summit_level {
+            for position in self.cells_at(level) {
+                let reached = self
+                    .downhill(&position)
+                    .any(|neighbour| network.contains(&neighbour));
+                if reached && trails.rating(&position) > 0 {
+                    network.insert(position);
+                }
+            }
+        }
+
+        network
+    }
+
+    /// Every cell of the map that is not on any trail, including the
+    /// impassable ones, top to bottom and left to right.
+    pub fn cells_off_trails(&self) -> Vec<Position> {
+        let network = self.trail_network();
+
+        self.positions()
+            .filter(|position| !network.contains(position))
+            .collect()
+    }
+
+    /// The longest path climbing at every step, by any number of levels
+    /// rather than exactly one as trails do. It may start and end anywhere.
+    pub fn longest_climb(&self) -> Vec<Position> {
+        // Longest climb starting at each cell, and the next cell on it
+        let mut lengths: HashMap<Position, usize> = HashMap::new();
+        let mut next: HashMap<Position, Position> = HashMap::new();
+        let mut cells: Vec<(&Position, &Level)> = self.levels.iter().collect();
+        cells.sort_by_key(|&(position, level)| (Reverse(*level), position.y, position.x));
+
+        for (position, level) in cells {
+            let best = position
                 .neighbours()
                 .into_iter()
-                .filter(|neighbour| self.is_valid(neighbour) && self.levels[neighbour] == level + 1)
-                .collect();
+                .filter(|neighbour| {
+                    self.levels
+                        .get(neighbour)
+                        .is_some_and(|other| other > level)
+                })
+                .max_by_key(|neighbour| (lengths[neighbour], Reverse((neighbour.y, neighbour.x))));
 
-            heads.extend(
-                to_visit
-                    .iter()
-                    .cloned()
-                    .map(|neighbour| (level + 1, neighbour)),
-            );
+            match best {
+                Some(neighbour) => {
+                    lengths.insert(*position, lengths[&neighbour] + 1);
+                    next.insert(*position, neighbour);
+                }
+                None => {
+                    lengths.insert(*position, 1);
+                }
+            }
         }
 
-        nines
+        let Some(start) = self
+            .positions()
+            .filter(|position| lengths.contains_key(position))
+            .max_by_key(|position| (lengths[position], Reverse((position.y, position.x))))
+        else {
+            return vec![];
+        };
+
+        let mut climb = vec![start];
+        while let Some(&position) = next.get(climb.last().unwrap()) {
+            climb.push(position);
+        }
+
+        climb
+    }
+
+    /// The map with only the cells on a trail, the rest shown as '.'
+    pub fn render_trail_network(&self) -> String {
+        let network = self.trail_network();
+        let mut rendered = String::new();
+
+        for y in 0..self.rows {
+            for x in 0..self.columns {
+                let position = Position::new(x as Coordinate, y as Coordinate);
+                match network.contains(&position) {
+                    true => {
+                        let level = self.levels[&position] as u32;
+                        rendered.push(char::from_digit(level, 36).unwrap());
+                    }
+                    false => rendered.push('.'),
+                }
+            }
+            rendered.push('\n');
+        }
+
+        rendered
+    }
+
+    fn positions(&self) -> impl Iterator<Item = Position> + '_ {
+        (0..self.rows).flat_map(move |y| {
+            (0..self.columns).map(move |x| Position::new(x as Coordinate, y as Coordinate))
+        })
+    }
+
+    /// Cells at the given level, top to bottom and left to right
+    fn cells_at(&self, level: Level) -> Vec<Position> {
+        let mut cells: Vec<Position> = self
+            .levels
+            .iter()
+            .filter(|(_, &other)| other == level)
+            .map(|(&position, _)| position)
+            .collect();
+        cells.sort_by_key(|position| (position.y, position.x));
+
+        cells
+    }
+
+    /// Neighbours exactly one level above the given position
+    fn uphill<'a>(&'a self, position: &Position) -> impl Iterator<Item = Position> + 'a {
+        let level = self.levels[position];
+
+        position.neighbours().into_iter().filter(move |neighbour| {
+            self.is_valid(neighbour) && self.levels.get(neighbour) == Some(&(level + 1))
+        })
+    }
+
+    /// Neighbours exactly one level below the given position
+    fn downhill<'a>(&'a self, position: &Position) -> impl Iterator<Item = Position> + 'a {
+        let level = self.levels[position];
+
+        position.neighbours().into_iter().filter(move |neighbour| {
+            self.is_valid(neighbour)
+                && level > 0
+                && self.levels.get(neighbour) == Some(&(level - 1))
+        })
     }
 
     fn is_valid(&self, position: &Position) -> bool {
@@ -104,6 +324,40 @@ impl Map {
     }
 }
 
+impl Trails {
+    /// Number of distinct summits reachable from a cell
+    pub fn score(&self, position: &Position) -> usize {
+        self.reachable
+            .get(position)
+            .map(|summit_set| {
+                summit_set
+                    .iter()
+                    .map(|word| word.count_ones() as usize)
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    /// Number of distinct trails from a cell to any summit
+    pub fn rating(&self, position: &Position) -> usize {
+        self.ratings.get(position).copied().unwrap_or(0)
+    }
+
+    /// Summits reachable from a cell, top to bottom and left to right
+    pub fn reachable_summits(&self, position: &Position) -> Vec<Position> {
+        let Some(summit_set) = self.reachable.get(position) else {
+            return vec![];
+        };
+
+        self.summits
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| summit_set[idx / 64] & (1 << (idx % 64)) != 0)
+            .map(|(_, summit)| *summit)
+            .collect()
+    }
+}
+
 impl FromStr for Map {
     type Err = ();
     fn from_str(input: &str) -> Result<Self, Self::Err> {
@@ -116,7 +370,7 @@ impl FromStr for Map {
                 line.chars().enumerate().filter_map(move |(x, c)| {
                     Some((
                         Position::new(x as Coordinate, y as Coordinate),
-                        c.to_digit(10)? as Level,
+                        c.to_digit(36)? as Level,
                     ))
                 })
             })
@@ -126,6 +380,191 @@ impl FromStr for Map {
             rows,
             columns,
             levels,
+            trailhead_level: 0,
+            summit_level: 9,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Rng;
+    use std::collections::HashSet;
+    use std::fs;
+
+    /// Walks every trail from a trailhead, as the puzzle describes it
+    fn walk_trails(map: &Map, trailhead: Position) -> (usize, usize) {
+        let mut heads = vec![trailhead];
+        let mut summits = HashSet::new();
+        let mut trails = 0;
+
+        while let Some(position) = heads.pop() {
+            if map.levels[&position] == map.summit_level {
+                summits.insert(position);
+                trails += 1;
+                continue;
+            }
+            heads.extend(map.uphill(&position));
+        }
+
+        (summits.len(), trails)
+    }
+
+    #[test]
+    fn test_total_score_and_rating() {
+        let input = fs::read_to_string("data/day10/test_input").unwrap();
+        let map: Map = input.parse().unwrap();
+
+        assert_eq!(36, map.total_score());
+        assert_eq!(81, map.total_rating());
+    }
+
+    #[test]
+    fn test_trailheads_summary() {
+        let input = fs::read_to_string("data/day10/test_input").unwrap();
+        let map: Map = input.parse().unwrap();
+        let summary = map.trailheads_summary();
+
+        assert_eq!(
+            vec![5, 6, 5, 3, 1, 3, 5, 3, 5],
+            summary.iter().map(|t| t.score).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![20, 24, 10, 4, 1, 4, 5, 8, 5],
+            summary.iter().map(|t| t.rating).collect::<Vec<_>>()
+        );
+        assert_eq!(Position::new(2, 0), summary[0].position);
+
+        let trails = map.trails();
+        assert_eq!(5, trails.reachable_summits(&Position::new(2, 0)).len());
+        // Cells off any trail have neither
+        assert_eq!(0, trails.score(&Position::new(100, 100)));
+    }
+
+    #[test]
+    fn test_reverse_queries() {
+        let input = fs::read_to_string("data/day10/test_input").unwrap();
+        let map: Map = input.parse().unwrap();
+        let summaries = map.trailheads_summary();
+
+        // Following every summit back gives the same score per trailhead
+        let trails = map.trails();
+        let mut fed: HashMap<Position, usize> = HashMap::new();
+        for summit in map.cells_at(9) {
+            for trailhead in map.trailheads_feeding(&summit) {
+                assert!(trails.reachable_summits(&trailhead).contains(&summit));
+                *fed.entry(trailhead).or_insert(0) += 1;
+            }
+        }
+        for summary in summaries {
+            assert_eq!(summary.score, fed[&summary.position]);
+            assert_eq!(
+                summary.score,
+                map.reachable_summits(&summary.position).len()
+            );
+        }
+        assert!(map.trailheads_feeding(&Position::new(0, 0)).is_empty());
+
+        // Climbing from one cell finds what the whole map pass found for it
+        for position in map.positions() {
+            assert_eq!(
+                trails.reachable_summits(&position),
+                map.reachable_summits(&position)
+            );
+        }
+        assert!(map.reachable_summits(&Position::new(-1, 0)).is_empty());
+    }
+
+    #[test]
+    fn test_trail_network() {
+        let map: Map = "0123\n1.34\n9995\n".parse().unwrap();
+        let map = map.with_levels(0, 4);
+
+        assert_eq!("0123\n..34\n....\n", map.render_trail_network());
+        assert_eq!(
+            vec![
+                Position::new(0, 1),
+                Position::new(1, 1),
+                Position::new(0, 2),
+                Position::new(1, 2),
+                Position::new(2, 2),
+                Position::new(3, 2),
+            ],
+            map.cells_off_trails()
+        );
+
+        let input = fs::read_to_string("data/day10/test_input").unwrap();
+        let map: Map = input.parse().unwrap();
+        let network = map.trail_network();
+        let off_trails = map.cells_off_trails();
+        assert_eq!(64, network.len() + off_trails.len());
+        assert!(off_trails.contains(&Position::new(0, 0)));
+    }
+
+    #[test]
+    fn test_longest_climb() {
+        let map: Map = "0129\n9.38\n8.47\n".parse().unwrap();
+        let climb: Vec<Level> = map
+            .longest_climb()
+            .iter()
+            .map(|position| map.levels[position])
+            .collect();
+
+        assert_eq!(vec![0, 1, 2, 3, 4, 7, 8, 9], climb);
+
+        let input = fs::read_to_string("data/day10/test_input").unwrap();
+        let map: Map = input.parse().unwrap();
+        let climb = map.longest_climb();
+        assert!(climb.len() >= 10);
+        assert!(climb
+            .windows(2)
+            .all(|pair| map.levels[&pair[0]] < map.levels[&pair[1]]
+                && pair[0].manhattan_distance(&pair[1]) == 1));
+    }
+
+    #[test]
+    fn test_levels() {
+        let map: Map = "0123\n1.34\nabc5\n".parse().unwrap();
+
+        assert_eq!(0, map.total_score());
+        let map = map.with_levels(0, 3);
+        assert_eq!(2, map.total_score());
+        assert_eq!(2, map.total_rating());
+
+        let map = map.with_levels(10, 12);
+        assert_eq!(1, map.total_score());
+    }
+
+    #[test]
+    fn test_against_walking() {
+        // Both ways around the square lead to the same summit
+        let map = "01\n12\n".parse::<Map>().unwrap().with_levels(0, 2);
+        let trailhead = map.trailheads_summary()[0];
+        assert_eq!((1, 2), (trailhead.score, trailhead.rating));
+        assert_eq!(walk_trails(&map, trailhead.position), (1, 2));
+
+        let mut rng = Rng::new(3);
+        let mut next = move |bound: u64| rng.below(bound);
+
+        for _ in 0..100 {
+            // Few distinct levels, so there are plenty of trails
+            let input: String = (0..12)
+                .map(|_| {
+                    let row: String = (0..12)
+                        .map(|_| char::from_digit(next(5) as u32, 10).unwrap())
+                        .collect();
+                    row + "\n"
+                })
+                .collect();
+            let map = input.parse::<Map>().unwrap().with_levels(0, 4);
+
+            for trailhead in map.trailheads_summary() {
+                assert_eq!(
+                    walk_trails(&map, trailhead.position),
+                    (trailhead.score, trailhead.rating)
+                );
+            }
+        }
+    }
+}
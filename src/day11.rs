@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 type Stone = u64;
 
@@ -10,40 +10,547 @@ pub fn parse_input(input: &str) -> Vec<Stone> {
 }
 
 pub fn simulate_stones(stones: &[Stone], blinks: usize) -> usize {
-    stones
-        .iter()
-        .map(|&stone| simulate_stone(stone, blinks))
-        .sum()
+    Simulator::new(PlutonianRule).count_after(stones, blinks) as usize
 }
 
-fn simulate_stone(stone: Stone, blinks: usize) -> usize {
-    let mut frequencies = HashMap::from([(stone, 1)]);
+/// What a single stone turns into after a blink
+pub trait BlinkRule {
+    fn blink(&self, stone: Stone) -> Vec<Stone>;
+}
+
+/// The rules from the puzzle: 0 becomes 1, stones with an even number of
+/// digits split in two halves, and any other one is multiplied by 2024.
+pub struct PlutonianRule;
+
+impl BlinkRule for PlutonianRule {
+    fn blink(&self, stone: Stone) -> Vec<Stone> {
+        if stone == 0 {
+            return vec![1];
+        }
+
+        let serialized = stone.to_string();
+        if serialized.len().is_multiple_of(2) {
+            let (left, right) = serialized.split_at(serialized.len() / 2);
+            return vec![left.parse().unwrap(), right.parse().unwrap()];
+        }
+
+        vec![stone * 2024]
+    }
+}
+
+/// Runs blinks under a rule, remembering what each stone turns into and how
+/// many stones it becomes after each number of blinks, for all the stones
+/// and calls alike.
+pub struct Simulator<R> {
+    rule: R,
+    blinks: HashMap<Stone, Vec<Stone>>,
+    counts: HashMap<(Stone, usize), u64>,
+}
+
+impl<R: BlinkRule> Simulator<R> {
+    pub fn new(rule: R) -> Self {
+        Self {
+            rule,
+            blinks: HashMap::new(),
+            counts: HashMap::new(),
+        }
+    }
+
+    pub fn count_after(&mut self, stones: &[Stone], blinks: usize) -> u64 {
+        stones
+            .iter()
+            .map(|&stone| self.count_stone_after(stone, blinks))
+            .sum()
+    }
+
+    /// How many stones of each value there are after the given blinks
+    pub fn distribution_after(&mut self, stones: &[Stone], blinks: usize) -> HashMap<Stone, u64> {
+        let mut frequencies: HashMap<Stone, u64> = HashMap::new();
+        for &stone in stones {
+            *frequencies.entry(stone).or_insert(0) += 1;
+        }
+
+        for _ in 0..blinks {
+            let mut updated_frequencies = HashMap::new();
+
+            for (stone, count) in frequencies {
+                for &next_stone in self.blink(stone).iter() {
+                    *updated_frequencies.entry(next_stone).or_insert(0) += count;
+                }
+            }
+
+            frequencies = updated_frequencies;
+        }
+
+        frequencies
+    }
+
+    /// Every value the stones can ever take, sorted, if there are at most
+    /// `max_values` of them. Under the puzzle rule all the stones end up
+    /// cycling through a few thousand values.
+    pub fn closed_set(&mut self, stones: &[Stone], max_values: usize) -> Option<Vec<Stone>> {
+        let mut values: HashSet<Stone> = stones.iter().copied().collect();
+        let mut pending: Vec<Stone> = values.iter().copied().collect();
+
+        while let Some(stone) = pending.pop() {
+            if values.len() > max_values {
+                return None;
+            }
+
+            for next_stone in self.blink(stone).to_vec() {
+                if values.insert(next_stone) {
+                    pending.push(next_stone);
+                }
+            }
+        }
+
+        let mut values: Vec<Stone> = values.into_iter().collect();
+        values.sort();
+
+        Some(values)
+    }
 
-    for _ in 0..blinks {
-        let mut updated_frequencies = HashMap::new();
+    /// The number of stones after any number of blinks, modulo the given
+    /// prime, as counts quickly outgrow any integer type. Nothing if the
+    /// modulus is not a prime or the closed set has more than max_values.
+    ///
+    /// Stones only take values in the closed set, so the counts follow a
+    /// linear recurrence no longer than the set. Blinking a pass at a time
+    /// over the few transitions of each value gives enough counts to find
+    /// it with Berlekamp-Massey, and then the count for any number of blinks
+    /// comes from raising x to that power modulo the recurrence polynomial,
+    /// with a number of polynomial products logarithmic in the blinks.
+    pub fn count_after_modulo(
+        &mut self,
+        stones: &[Stone],
+        blinks: u64,
+        modulus: u64,
+        max_values: usize,
+    ) -> Option<u64> {
+        let field = PrimeField::new(modulus)?;
+        let values = self.closed_set(stones, max_values)?;
+        let index: HashMap<Stone, usize> = values
+            .iter()
+            .enumerate()
+            .map(|(idx, &stone)| (stone, idx))
+            .collect();
 
-        for (stone, count) in frequencies {
-            for next_stone in blink(stone) {
-                *updated_frequencies.entry(next_stone).or_insert(0) += count;
+        // What each value turns into, as indices into the closed set
+        let transitions: Vec<Vec<usize>> = values
+            .iter()
+            .map(|&stone| {
+                self.blink(stone)
+                    .iter()
+                    .map(|next_stone| index[next_stone])
+                    .collect()
+            })
+            .collect();
+
+        let mut counts = vec![0; values.len()];
+        for stone in stones {
+            counts[index[stone]] = field.add(counts[index[stone]], 1);
+        }
+
+        // Twice the length of the longest possible recurrence pins it down
+        let needed = blinks.min(2 * values.len() as u64);
+        let mut totals = vec![counts
+            .iter()
+            .fold(0, |total, &count| field.add(total, count))];
+        for _ in 0..needed {
+            let mut updated_counts = vec![0; values.len()];
+            for (idx, &count) in counts.iter().enumerate() {
+                if count == 0 {
+                    continue;
+                }
+                for &next_idx in transitions[idx].iter() {
+                    updated_counts[next_idx] = field.add(updated_counts[next_idx], count);
+                }
             }
+            counts = updated_counts;
+            totals.push(
+                counts
+                    .iter()
+                    .fold(0, |total, &count| field.add(total, count)),
+            );
+        }
+
+        if blinks < totals.len() as u64 {
+            return Some(totals[blinks as usize]);
         }
 
-        frequencies = updated_frequencies;
+        let recurrence = field.berlekamp_massey(&totals);
+        let combination = field.power_of_x(blinks, &recurrence);
+
+        Some(field.dot(combination.into_iter().zip(totals)))
     }
 
-    frequencies.iter().map(|(_, count)| count).sum()
+    fn count_stone_after(&mut self, stone: Stone, blinks: usize) -> u64 {
+        if blinks == 0 {
+            return 1;
+        }
+        if let Some(&count) = self.counts.get(&(stone, blinks)) {
+            return count;
+        }
+
+        let count = self
+            .blink(stone)
+            .to_vec()
+            .into_iter()
+            .map(|next_stone| self.count_stone_after(next_stone, blinks - 1))
+            .sum();
+        self.counts.insert((stone, blinks), count);
+
+        count
+    }
+
+    fn blink(&mut self, stone: Stone) -> &[Stone] {
+        self.blinks
+            .entry(stone)
+            .or_insert_with(|| self.rule.blink(stone))
+    }
 }
 
-fn blink(stone: Stone) -> Vec<Stone> {
-    if stone == 0 {
-        return vec![1];
+/// Arithmetic modulo a prime
+#[derive(Clone, Copy)]
+struct PrimeField {
+    prime: u64,
+}
+
+impl PrimeField {
+    fn new(prime: u64) -> Option<Self> {
+        is_prime(prime).then_some(Self { prime })
+    }
+
+    fn add(&self, left: u64, right: u64) -> u64 {
+        // Both terms are already reduced, so one subtraction reduces the sum
+        let (sum, overflow) = left.overflowing_add(right);
+        match overflow || sum >= self.prime {
+            true => sum.wrapping_sub(self.prime),
+            false => sum,
+        }
     }
 
-    let serialized = stone.to_string();
-    if serialized.len() % 2 == 0 {
-        let (left, right) = serialized.split_at(serialized.len() / 2);
-        return vec![left.parse().unwrap(), right.parse().unwrap()];
+    fn sub(&self, left: u64, right: u64) -> u64 {
+        match left >= right {
+            true => left - right,
+            false => left + (self.prime - right),
+        }
     }
 
-    vec![stone * 2024]
+    fn mul(&self, left: u64, right: u64) -> u64 {
+        mul_mod(left, right, self.prime)
+    }
+
+    fn inverse(&self, value: u64) -> u64 {
+        pow_mod(value, self.prime - 2, self.prime)
+    }
+
+    /// Adds a product to a sum that is only reduced when it would overflow,
+    /// which for primes below 2^64 still leaves room for the product
+    fn accumulate(&self, sum: &mut u128, left: u64, right: u64) {
+        let product = left as u128 * right as u128;
+        *sum = match sum.checked_add(product) {
+            Some(total) => total,
+            None => *sum % self.prime as u128 + product,
+        };
+    }
+
+    fn reduce(&self, sum: u128) -> u64 {
+        (sum % self.prime as u128) as u64
+    }
+
+    fn dot<I: IntoIterator<Item = (u64, u64)>>(&self, pairs: I) -> u64 {
+        let mut sum = 0;
+        for (left, right) in pairs {
+            self.accumulate(&mut sum, left, right);
+        }
+
+        self.reduce(sum)
+    }
+
+    /// The shortest recurrence the terms follow, as the coefficients c such
+    /// that terms[n] = c[0] terms[n - 1] + ... + c[l - 1] terms[n - l]
+    fn berlekamp_massey(&self, terms: &[u64]) -> Vec<u64> {
+        // Connection polynomials, with 1 for their constant coefficient
+        let mut current = vec![1];
+        let mut previous = vec![1];
+        let mut length = 0;
+        let mut previous_discrepancy = 1;
+        let mut shift = 0;
+
+        for (n, &term) in terms.iter().enumerate() {
+            shift += 1;
+            let discrepancy = self.add(
+                term,
+                self.dot((1..=length).map(|idx| (current[idx], terms[n - idx]))),
+            );
+            if discrepancy == 0 {
+                continue;
+            }
+
+            let factor = self.mul(discrepancy, self.inverse(previous_discrepancy));
+            let updated = {
+                let mut updated = current.clone();
+                updated.resize(updated.len().max(previous.len() + shift), 0);
+                for (idx, &coefficient) in previous.iter().enumerate() {
+                    let entry = &mut updated[idx + shift];
+                    *entry = self.sub(*entry, self.mul(factor, coefficient));
+                }
+                updated
+            };
+
+            if 2 * length <= n {
+                length = n + 1 - length;
+                previous = current;
+                previous_discrepancy = discrepancy;
+                shift = 0;
+            }
+            current = updated;
+        }
+
+        current.resize(length + 1, 0);
+        current[1..]
+            .iter()
+            .map(|&coefficient| self.sub(0, coefficient))
+            .collect()
+    }
+
+    /// x to the given power modulo the polynomial of the recurrence, that
+    /// is x^l - c[0] x^(l - 1) - ... - c[l - 1]. Its coefficients are how
+    /// much of each of the first l terms make up the term at that power.
+    fn power_of_x(&self, exponent: u64, recurrence: &[u64]) -> Vec<u64> {
+        let length = recurrence.len();
+        if length == 0 {
+            return vec![];
+        }
+
+        // Reduces a polynomial of degree below 2l, one leading term at a time
+        let reduce = |mut coefficients: Vec<u128>| -> Vec<u64> {
+            for degree in (length..coefficients.len()).rev() {
+                let leading = self.reduce(coefficients[degree]);
+                for (idx, &coefficient) in recurrence.iter().enumerate() {
+                    self.accumulate(&mut coefficients[degree - 1 - idx], leading, coefficient);
+                }
+            }
+            coefficients[..length]
+                .iter()
+                .map(|&coefficient| self.reduce(coefficient))
+                .collect()
+        };
+
+        let mut power = vec![0; length];
+        power[0] = 1;
+        for bit in (0..u64::BITS - exponent.leading_zeros()).rev() {
+            let mut squared = vec![0; 2 * length - 1];
+            for (left_degree, &left) in power.iter().enumerate() {
+                if left == 0 {
+                    continue;
+                }
+                for (right_degree, &right) in power.iter().enumerate() {
+                    self.accumulate(&mut squared[left_degree + right_degree], left, right);
+                }
+            }
+            let mut next = reduce(squared);
+
+            if exponent >> bit & 1 == 1 {
+                let mut shifted: Vec<u128> = next
+                    .iter()
+                    .map(|&coefficient| coefficient as u128)
+                    .collect();
+                shifted.insert(0, 0);
+                next = reduce(shifted);
+            }
+            power = next;
+        }
+
+        power
+    }
+}
+
+fn mul_mod(left: u64, right: u64, modulus: u64) -> u64 {
+    (left as u128 * right as u128 % modulus as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exponent: u64, modulus: u64) -> u64 {
+    let mut result = 1 % modulus;
+    base %= modulus;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, modulus);
+        }
+        base = mul_mod(base, base, modulus);
+        exponent >>= 1;
+    }
+
+    result
+}
+
+/// Miller-Rabin with the bases that make it exact for every u64
+fn is_prime(n: u64) -> bool {
+    const BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    if let Some(&base) = BASES.iter().find(|&&base| n.is_multiple_of(base)) {
+        return n == base;
+    }
+
+    let twos = (n - 1).trailing_zeros();
+    let odd = (n - 1) >> twos;
+    BASES.iter().all(|&base| {
+        let mut witness = pow_mod(base, odd, n);
+        if witness == 1 || witness == n - 1 {
+            return true;
+        }
+        (1..twos).any(|_| {
+            witness = mul_mod(witness, witness, n);
+            witness == n - 1
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_simulate_stones() {
+        let input = fs::read_to_string("data/day11/test_input").unwrap();
+        let stones = parse_input(&input);
+
+        assert_eq!(22, simulate_stones(&stones, 6));
+        assert_eq!(55312, simulate_stones(&stones, 25));
+    }
+
+    #[test]
+    fn test_distribution_after() {
+        let mut simulator = Simulator::new(PlutonianRule);
+        let distribution = simulator.distribution_after(&[125, 17], 6);
+
+        // 2097446912 14168 4048 2 0 2 4 40 48 2024 40 48 80 96 2 8 6 7 6 0 3 2
+        assert_eq!(Some(&4), distribution.get(&2));
+        assert_eq!(Some(&2), distribution.get(&40));
+        assert_eq!(Some(&1), distribution.get(&2097446912));
+        assert_eq!(22, distribution.values().sum::<u64>());
+        assert_eq!(22, simulator.count_after(&[125, 17], 6));
+    }
+
+    #[test]
+    fn test_custom_rule() {
+        struct Halving;
+        impl BlinkRule for Halving {
+            fn blink(&self, stone: Stone) -> Vec<Stone> {
+                match stone {
+                    0 => vec![],
+                    1 => vec![0, 0],
+                    _ => vec![stone / 2, stone / 2],
+                }
+            }
+        }
+
+        let mut simulator = Simulator::new(Halving);
+        assert_eq!(8, simulator.count_after(&[4], 3));
+        assert_eq!(0, simulator.count_after(&[4], 4));
+        assert_eq!(Some(vec![0, 1, 2, 4]), simulator.closed_set(&[4], 10));
+    }
+
+    #[test]
+    fn test_closed_set() {
+        let mut simulator = Simulator::new(PlutonianRule);
+        let values = simulator.closed_set(&[0], 1000).unwrap();
+
+        assert_eq!(54, values.len());
+        assert!(values.contains(&2024) && values.contains(&16192));
+        assert_eq!(None, simulator.closed_set(&[0], 10));
+    }
+
+    /// Blinks one at a time, keeping counts modulo the given number
+    fn blink_modulo(stones: &[Stone], blinks: usize, modulus: u64) -> u64 {
+        let add = |left: u64, right: u64| ((left as u128 + right as u128) % modulus as u128) as u64;
+        let mut frequencies: HashMap<Stone, u64> = HashMap::new();
+        for &stone in stones {
+            let count = frequencies.entry(stone).or_insert(0);
+            *count = add(*count, 1);
+        }
+
+        for _ in 0..blinks {
+            let mut updated_frequencies = HashMap::new();
+            for (stone, count) in frequencies {
+                for next_stone in PlutonianRule.blink(stone) {
+                    let next_count = updated_frequencies.entry(next_stone).or_insert(0);
+                    *next_count = add(*next_count, count);
+                }
+            }
+            frequencies = updated_frequencies;
+        }
+
+        frequencies.into_values().fold(0, add)
+    }
+
+    #[test]
+    fn test_count_after_modulo() {
+        let mut simulator = Simulator::new(PlutonianRule);
+        let modulus = 1_000_000_007;
+
+        for blinks in [0, 1, 10, 30, 75] {
+            let exact = simulator.count_after(&[0, 1], blinks as usize);
+            assert_eq!(
+                Some(exact % modulus),
+                simulator.count_after_modulo(&[0, 1], blinks, modulus, 1000)
+            );
+        }
+
+        // Well past the counts the recurrence is found from
+        for blinks in [200, 10_000] {
+            assert_eq!(
+                Some(blink_modulo(&[0], blinks, modulus)),
+                simulator.count_after_modulo(&[0], blinks as u64, modulus, 1000)
+            );
+        }
+
+        // The largest prime below 2^64, where sums and products overflow
+        let modulus = 18446744073709551557;
+        assert_eq!(
+            Some(blink_modulo(&[0, 17], 500, modulus)),
+            simulator.count_after_modulo(&[0, 17], 500, modulus, 1000)
+        );
+
+        for not_prime in [0, 1, 4, 1_000_000_000, 18446744073709551615] {
+            assert_eq!(
+                None,
+                simulator.count_after_modulo(&[0], 10, not_prime, 1000)
+            );
+        }
+
+        // A 0 is a 1 one blink later
+        let modulus = 998_244_353;
+        assert_eq!(
+            simulator.count_after_modulo(&[1], 999_999_999, modulus, 1000),
+            simulator.count_after_modulo(&[0], 1_000_000_000, modulus, 1000)
+        );
+    }
+
+    #[test]
+    fn test_count_after_modulo_real_input() {
+        let input = fs::read_to_string("data/day11/input").unwrap();
+        let stones = parse_input(&input);
+        let mut simulator = Simulator::new(PlutonianRule);
+        let modulus = 1_000_000_007;
+
+        let values = simulator.closed_set(&stones, 10_000).unwrap();
+        assert_eq!(3956, values.len());
+
+        let exact = simulator.count_after(&stones, 75);
+        assert_eq!(
+            Some(exact % modulus),
+            simulator.count_after_modulo(&stones, 75, modulus, 10_000)
+        );
+
+        // A million blinks, checked against blinking one pass at a time
+        assert_eq!(
+            Some(907427190),
+            simulator.count_after_modulo(&stones, 1_000_000, modulus, 10_000)
+        );
+    }
 }
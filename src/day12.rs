@@ -1,35 +1,36 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::str::FromStr;
 
 use crate::parse::TextGrid;
-use crate::spatial::{Direction, Orientation, Point2D};
+use crate::spatial::{Direction, Orientation, Point2D, Rectangle};
 
 pub struct Farm {
     text_grid: TextGrid,
 }
 
-type Position = Point2D<Coordinate>;
-type Coordinate = i16;
+/// Plots are at (column, row) with rows counted from the bottom of the map,
+/// so y grows upwards like directions expect.
+pub type Position = Point2D<Coordinate>;
+pub type Coordinate = i16;
+pub type BoundingBox = Rectangle<Coordinate>;
 
 impl Farm {
     pub fn total_fence_price(&self) -> u64 {
-        self.as_regions().iter().map(Region::fence_price).sum()
+        self.regions().iter().map(Region::fence_price).sum()
     }
 
     pub fn total_bulk_discount_price(&self) -> u64 {
-        self.as_regions()
-            .iter()
-            .map(Region::bulk_discount_price)
-            .sum()
+        self.regions().iter().map(Region::bulk_discount_price).sum()
     }
 
-    fn as_regions(&self) -> Vec<Region> {
+    /// Every region in the farm, from the bottom left plot onwards
+    pub fn regions(&self) -> Vec<Region> {
         let mut known: HashSet<Position> = HashSet::new();
         let mut regions = vec![];
 
         for row in 0..self.text_grid.height {
             for column in 0..self.text_grid.width {
-                let position = Position::new(row as Coordinate, column as Coordinate);
+                let position = Position::new(column as Coordinate, row as Coordinate);
 
                 // Already part of a region, no need to explore
                 if known.contains(&position) {
@@ -64,10 +65,64 @@ impl Farm {
 
             pending.extend(neighbours.iter().cloned());
             plots.extend(neighbours.iter().cloned());
-            seen.extend(neighbours.into_iter());
+            seen.extend(neighbours);
+        }
+
+        Region {
+            label: plant_label,
+            plots,
+        }
+    }
+
+    /// Pairs of indices in `regions` where the first region lies inside one
+    /// of the holes of the second one, so it cannot be reached from the edge
+    /// of the map without crossing the second region. Like border walking,
+    /// plots touching at a corner are considered connected, so a gap at a
+    /// corner of the second region is not a hole. Regions nested in several
+    /// others are enclosed by each of them.
+    pub fn enclosures(&self) -> Vec<Enclosure> {
+        let regions = self.regions();
+        let region_of: HashMap<Position, usize> = regions
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, region)| region.plots.iter().map(move |&plot| (plot, idx)))
+            .collect();
+        let mut enclosures = vec![];
+
+        for (outer, region) in regions.iter().enumerate() {
+            // Nothing outside the box around the region can be in a hole, so
+            // flood from its frame and whatever is not reached is enclosed
+            let bounding_box = region.bounding_box();
+            let area = bounding_box.grown(1);
+            let mut pending: Vec<Position> = area
+                .points()
+                .filter(|position| !bounding_box.contains(position))
+                .collect();
+            let mut reached: HashSet<Position> = pending.iter().copied().collect();
+
+            while let Some(current) = pending.pop() {
+                let neighbours =
+                    (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| current + Position::new(dx, dy)));
+                for neighbour in neighbours {
+                    if area.contains(&neighbour)
+                        && !region.plots.contains(&neighbour)
+                        && reached.insert(neighbour)
+                    {
+                        pending.push(neighbour);
+                    }
+                }
+            }
+
+            let inner: BTreeSet<usize> = area
+                .points()
+                .filter(|position| !reached.contains(position))
+                .filter_map(|position| region_of.get(&position).copied())
+                .filter(|&inner| inner != outer)
+                .collect();
+            enclosures.extend(inner.into_iter().map(|inner| Enclosure { inner, outer }));
         }
 
-        Region { plots }
+        enclosures
     }
 
     fn is_in_region(&self, position: &Position, label: char) -> bool {
@@ -85,20 +140,83 @@ impl Farm {
     }
 }
 
-struct Region {
+/// A region in `Farm::enclosures`, given by its index in `Farm::regions`,
+/// that lies inside another one
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Enclosure {
+    pub inner: usize,
+    pub outer: usize,
+}
+
+pub struct Region {
+    label: char,
     plots: HashSet<Position>,
 }
 
 impl Region {
-    fn fence_price(&self) -> u64 {
+    pub fn label(&self) -> char {
+        self.label
+    }
+
+    pub fn plots(&self) -> &HashSet<Position> {
+        &self.plots
+    }
+
+    pub fn fence_price(&self) -> u64 {
         self.perimeter() * self.area()
     }
 
-    fn bulk_discount_price(&self) -> u64 {
+    pub fn bulk_discount_price(&self) -> u64 {
         self.sides() * self.area()
     }
 
-    fn perimeter(&self) -> u64 {
+    pub fn bounding_box(&self) -> BoundingBox {
+        let min_x = self.plots.iter().map(|plot| plot.x).min().unwrap();
+        let min_y = self.plots.iter().map(|plot| plot.y).min().unwrap();
+        let max_x = self.plots.iter().map(|plot| plot.x).max().unwrap();
+        let max_y = self.plots.iter().map(|plot| plot.y).max().unwrap();
+
+        BoundingBox {
+            min: Position::new(min_x, min_y),
+            max: Position::new(max_x, max_y),
+        }
+    }
+
+    /// Number of borders inside the region, each one around a hole holding
+    /// one or more other regions. Holes touching each other or the outside
+    /// at a corner share their border.
+    pub fn holes(&self) -> usize {
+        self.borders().len() - 1
+    }
+
+    /// Corners of each border of the region, in the order they are walked
+    /// from the smallest one, starting with the outer border and then the
+    /// ones around holes. Corners
+    /// are lattice points, with plot (x, y) spanning from (x, y) to
+    /// (x + 1, y + 1).
+    pub fn border_vertices(&self) -> Vec<Vec<Position>> {
+        let mut borders = self.borders();
+        let outer = self.outer_boundary_position();
+        let outer_idx = borders
+            .iter()
+            .position(|border| border.contains(&outer))
+            .unwrap();
+        borders.swap(0, outer_idx);
+        let mut vertices: Vec<Vec<Position>> = borders
+            .iter()
+            .map(|border| {
+                let mut corners = self.corners(border);
+                let first = (0..corners.len()).min_by_key(|&idx| corners[idx]).unwrap();
+                corners.rotate_left(first);
+                corners
+            })
+            .collect();
+        vertices[1..].sort_by_key(|corners| corners[0]);
+
+        vertices
+    }
+
+    pub fn perimeter(&self) -> u64 {
         self.plots
             .iter()
             .map(|plot| {
@@ -110,11 +228,40 @@ impl Region {
             .sum()
     }
 
-    fn area(&self) -> u64 {
+    pub fn area(&self) -> u64 {
         self.plots.len() as u64
     }
 
-    fn sides(&self) -> u64 {
+    /// Every border has as many sides as corners, so this counts the corners
+    /// around each plot: convex ones where both neighbours towards the corner
+    /// are outside the region, and concave ones where both are inside but
+    /// the diagonal plot between them is not. Unlike walking the borders it
+    /// takes linear time in the area of the region.
+    pub fn sides(&self) -> u64 {
+        self.plots
+            .iter()
+            .map(|&plot| {
+                Direction::all()
+                    .into_iter()
+                    .filter(|&direction| {
+                        let first = Position::from(direction);
+                        let second = Position::from(direction.rotate(Orientation::Clockwise));
+                        let is_first_inside = self.plots.contains(&(plot + first));
+                        let is_second_inside = self.plots.contains(&(plot + second));
+                        let is_diagonal_inside = self.plots.contains(&(plot + first + second));
+
+                        (!is_first_inside && !is_second_inside)
+                            || (is_first_inside && is_second_inside && !is_diagonal_inside)
+                    })
+                    .count() as u64
+            })
+            .sum()
+    }
+
+    /// Number of sides found by walking every border of the region, kept
+    /// to check the corner count against
+    #[cfg(test)]
+    fn walked_sides(&self) -> u64 {
         // Regions are not solid and so may have multiple borders inside.
         // For each of those we need to count the number of sides, and add them together.
         self.borders().iter().map(|border| border.sides()).sum()
@@ -155,6 +302,42 @@ impl Region {
         borders
     }
 
+    /// The corner where each side starts. Sides found by turning around a
+    /// concave corner are stored one plot behind the start of the side.
+    fn corners(&self, border: &Border) -> Vec<Position> {
+        border
+            .vertices
+            .iter()
+            .map(|side_start| {
+                let normal = Position::from(side_start.normal);
+                let along = Position::from(side_start.normal.rotate(Orientation::Clockwise));
+                let corner = side_start.plot
+                    + Position::new((normal.x - along.x + 1) / 2, (normal.y - along.y + 1) / 2);
+
+                if self.plots.contains(&side_start.outside()) {
+                    corner + along
+                } else {
+                    corner
+                }
+            })
+            .collect()
+    }
+
+    /// The bottom side of the lowest leftmost plot, which can only be on the
+    /// outer border
+    fn outer_boundary_position(&self) -> BoundaryPosition {
+        let plot = *self
+            .plots
+            .iter()
+            .min_by_key(|plot| (plot.y, plot.x))
+            .unwrap();
+
+        BoundaryPosition {
+            plot,
+            normal: Direction::Down,
+        }
+    }
+
     /// Given a point in the boundary of a region, collect all the vertices in that border
     fn complete_border(&self, boundary_position: BoundaryPosition) -> Border {
         // Move to the next start point since we might be starting from the middle of a side
@@ -237,6 +420,7 @@ struct Border {
 }
 
 impl Border {
+    #[cfg(test)]
     fn sides(&self) -> u64 {
         self.vertices.len() as u64
     }
@@ -289,3 +473,215 @@ impl FromStr for Farm {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Rng;
+    use std::fs;
+
+    fn read_farm(name: &str) -> Farm {
+        fs::read_to_string(format!("data/day12/{}", name))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_prices() {
+        let farm = read_farm("test_input");
+
+        assert_eq!(1930, farm.total_fence_price());
+        assert_eq!(1206, farm.total_bulk_discount_price());
+    }
+
+    #[test]
+    fn test_sides_by_corners() {
+        for entry in fs::read_dir("data/day12").unwrap() {
+            let path = entry.unwrap().path();
+            let farm: Farm = fs::read_to_string(path).unwrap().parse().unwrap();
+
+            for region in farm.regions() {
+                assert_eq!(region.walked_sides(), region.sides());
+            }
+        }
+    }
+
+    #[test]
+    fn test_sides_on_random_farms() {
+        // Two B regions touch only at a corner, and A walls both of them in
+        let farm: Farm = "AAAAAA\nAAABBA\nAAABBA\nABBAAA\nABBAAA\nAAAAAA"
+            .parse()
+            .unwrap();
+        let outer = farm
+            .regions()
+            .into_iter()
+            .find(|region| region.label() == 'A')
+            .unwrap();
+        assert_eq!((12, 12), (outer.walked_sides(), outer.sides()));
+
+        let mut rng = Rng::new(12);
+        let mut next = move |bound: u64| rng.below(bound);
+
+        for _ in 0..500 {
+            let (width, height, plants) = (1 + next(8), 2 + next(7), 1 + next(4));
+            let rows: Vec<String> = (0..height)
+                .map(|_| {
+                    (0..width)
+                        .map(|_| (b'A' + next(plants) as u8) as char)
+                        .collect()
+                })
+                .collect();
+            let farm: Farm = rows.join("\n").parse().unwrap();
+
+            for region in farm.regions() {
+                assert_eq!(region.walked_sides(), region.sides(), "{:?}", rows);
+            }
+        }
+    }
+
+    #[test]
+    fn test_region_geometry() {
+        let farm = read_farm("test_input.tiny");
+        let summary: Vec<_> = farm
+            .regions()
+            .iter()
+            .map(|region| {
+                (
+                    region.label(),
+                    region.area(),
+                    region.perimeter(),
+                    region.sides(),
+                    region.holes(),
+                )
+            })
+            .collect();
+
+        // Rows are counted from the bottom, so the E region comes first
+        assert_eq!(
+            vec![
+                ('E', 3, 8, 4, 0),
+                ('C', 4, 10, 8, 0),
+                ('B', 4, 8, 4, 0),
+                ('D', 1, 4, 4, 0),
+                ('A', 4, 10, 4, 0),
+            ],
+            summary
+        );
+
+        let c = &farm.regions()[1];
+        assert_eq!(
+            BoundingBox {
+                min: Position::new(2, 0),
+                max: Position::new(3, 2),
+            },
+            c.bounding_box()
+        );
+        assert_eq!(
+            vec![vec![
+                Position::new(2, 1),
+                Position::new(2, 3),
+                Position::new(3, 3),
+                Position::new(3, 2),
+                Position::new(4, 2),
+                Position::new(4, 0),
+                Position::new(3, 0),
+                Position::new(3, 1),
+            ]],
+            c.border_vertices()
+        );
+        assert!(farm.enclosures().is_empty());
+    }
+
+    #[test]
+    fn test_holes_and_enclosures() {
+        let farm = read_farm("test_input.holes");
+        let regions = farm.regions();
+
+        assert_eq!(('O', 21, 36, 20, 4), {
+            let o = &regions[0];
+            (o.label(), o.area(), o.perimeter(), o.sides(), o.holes())
+        });
+        let vertices = regions[0].border_vertices();
+        assert_eq!(5, vertices.len());
+        assert_eq!(
+            vec![
+                Position::new(0, 0),
+                Position::new(0, 5),
+                Position::new(5, 5),
+                Position::new(5, 0),
+            ],
+            vertices[0]
+        );
+        assert_eq!(
+            vec![
+                Position::new(1, 1),
+                Position::new(2, 1),
+                Position::new(2, 2),
+                Position::new(1, 2),
+            ],
+            vertices[1]
+        );
+        assert_eq!(
+            (1..=4)
+                .map(|inner| Enclosure { inner, outer: 0 })
+                .collect::<Vec<_>>(),
+            farm.enclosures()
+        );
+
+        // The X regions reach the edge of the map
+        let farm = read_farm("test_input.ex");
+        let regions = farm.regions();
+        assert_eq!((17, 36, 12, 0), {
+            let e = &regions[0];
+            (e.area(), e.perimeter(), e.sides(), e.holes())
+        });
+        assert_eq!(12, regions[0].border_vertices()[0].len());
+        assert!(farm.enclosures().is_empty());
+
+        // Both B regions touch at a corner, so they share a single hole
+        let farm = read_farm("test_input.ab");
+        let regions = farm.regions();
+        assert_eq!((28, 40, 12, 1), {
+            let a = &regions[0];
+            (a.area(), a.perimeter(), a.sides(), a.holes())
+        });
+        assert_eq!(
+            vec![
+                Enclosure { inner: 1, outer: 0 },
+                Enclosure { inner: 2, outer: 0 },
+            ],
+            farm.enclosures()
+        );
+        let vertices = regions[0].border_vertices();
+        assert_eq!(
+            vec![4, 8],
+            vertices.iter().map(Vec::len).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            2,
+            vertices[1]
+                .iter()
+                .filter(|&&vertex| vertex == Position::new(3, 3))
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_nested_enclosures() {
+        let farm: Farm = "AAAAA\nABBBA\nABCBA\nABBBA\nAAAAA".parse().unwrap();
+        let regions = farm.regions();
+        let labels: Vec<char> = regions.iter().map(Region::label).collect();
+        let enclosures: Vec<(char, char)> = farm
+            .enclosures()
+            .iter()
+            .map(|enclosure| (labels[enclosure.inner], labels[enclosure.outer]))
+            .collect();
+
+        assert_eq!(vec![('B', 'A'), ('C', 'A'), ('C', 'B')], enclosures);
+        assert_eq!(
+            vec![1, 1, 0],
+            regions.iter().map(Region::holes).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use std::str::FromStr;
 
 use crate::common::bezout;
-use crate::spatial::{Point2D, Point2DCast};
+use crate::spatial::Point2D;
 
-const PART_2_PRIZE_DELTA: usize = 10000000000000;
+/// Tokens for pressing A and B when parsing, any further button costs 1
+const DEFAULT_COSTS: [usize; 2] = [3, 1];
 
 pub fn parse_input(input: &str) -> Vec<Machine> {
     input
@@ -13,46 +14,79 @@ pub fn parse_input(input: &str) -> Vec<Machine> {
 }
 
 pub type Position = Point2D<Coordinate>;
-type Move = Point2D<Coordinate>;
+pub type Move = Point2D<Coordinate>;
 type Coordinate = usize;
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Button {
+    pub movement: Move,
+    pub cost: usize,
+    /// How many times the button can be pressed at most, if limited
+    pub limit: Option<usize>,
+}
+
+impl Button {
+    pub fn new(movement: Move, cost: usize) -> Self {
+        Self {
+            movement,
+            cost,
+            limit: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Machine {
-    pub button_a: Move,
-    pub button_b: Move,
+    pub buttons: Vec<Button>,
     pub prize: Position,
 }
 
+/// How many times each button of a machine is pressed, in order, and the
+/// tokens it takes
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Presses {
+    pub counts: Vec<usize>,
+    pub tokens: usize,
+}
+
 impl Machine {
-    pub fn required_tokens(&self) -> Option<usize> {
-        let eq_a = PositiveDiophantineEquation {
-            a: self.button_a.x,
-            b: self.button_b.x,
-            c: self.prize.x,
-        };
-        let eq_b = PositiveDiophantineEquation {
-            a: self.button_a.y,
-            b: self.button_b.y,
-            c: self.prize.y,
-        };
+    /// The same machine with every button limited to the given presses
+    pub fn with_press_limit(mut self, limit: usize) -> Self {
+        for button in self.buttons.iter_mut() {
+            button.limit = Some(limit);
+        }
 
-        eq_a.intersect(&eq_b)
-            .map(|solution| 3 * solution.x + solution.y)
+        self
     }
 
-    pub fn required_tokens_adjusted(&self) -> Option<usize> {
-        let eq_a = PositiveDiophantineEquation {
-            a: self.button_a.x,
-            b: self.button_b.x,
-            c: self.prize.x + PART_2_PRIZE_DELTA,
-        };
-        let eq_b = PositiveDiophantineEquation {
-            a: self.button_a.y,
-            b: self.button_b.y,
-            c: self.prize.y + PART_2_PRIZE_DELTA,
-        };
+    /// The same machine with the given cost for each button, in order
+    pub fn with_costs(mut self, costs: &[usize]) -> Self {
+        for (button, &cost) in self.buttons.iter_mut().zip(costs) {
+            button.cost = cost;
+        }
+
+        self
+    }
+
+    pub fn required_tokens(&self, prize_offset: Coordinate) -> Option<usize> {
+        self.cheapest_presses(prize_offset)
+            .map(|presses| presses.tokens)
+    }
+
+    /// The presses winning the prize, moved by the offset on both axes, for
+    /// the fewest tokens. Two buttons are solved exactly, and any others are
+    /// enumerated within their limits, so machines with more than two
+    /// buttons need small limits or prizes to be solved quickly.
+    pub fn cheapest_presses(&self, prize_offset: Coordinate) -> Option<Presses> {
+        let target = self.prize + Position::new(prize_offset, prize_offset);
+        let counts = Solver::new(&self.buttons).solve(target)?;
+        let tokens = counts
+            .iter()
+            .zip(self.buttons.iter())
+            .map(|(count, button)| count * button.cost)
+            .sum();
 
-        eq_a.intersect(&eq_b)
-            .map(|solution| 3 * solution.x + solution.y)
+        Some(Presses { counts, tokens })
     }
 
     fn parse_line(button_line: &str) -> Move {
@@ -70,82 +104,452 @@ impl FromStr for Machine {
 
     fn from_str(machine_block: &str) -> Result<Self, Self::Err> {
         let lines: Vec<_> = machine_block.lines().collect();
-        let button_a = Machine::parse_line(lines[0]);
-        let button_b = Machine::parse_line(lines[1]);
-        let prize = Machine::parse_line(lines[2]);
+        let (prize_line, button_lines) = lines.split_last().ok_or(())?;
+        if !prize_line.starts_with("Prize") {
+            return Err(());
+        }
+
+        let buttons = button_lines
+            .iter()
+            .enumerate()
+            .map(|(idx, line)| {
+                let cost = DEFAULT_COSTS.get(idx).copied().unwrap_or(1);
+                Button::new(Machine::parse_line(line), cost)
+            })
+            .collect();
+        let prize = Machine::parse_line(prize_line);
 
-        Ok(Machine {
-            button_a,
-            button_b,
-            prize,
-        })
+        Ok(Machine { buttons, prize })
     }
 }
 
-/// Represents the equation α * a + β * b = c / a, b, c >= 0
-/// Solves for α, β >= 0
-struct PositiveDiophantineEquation {
-    a: Coordinate,
-    b: Coordinate,
-    c: Coordinate,
+/// Buttons whose presses are found directly once the others are chosen
+enum Basis {
+    /// Linearly independent buttons, with a single solution
+    Independent(usize, usize),
+    /// Buttons moving along the same line, with solutions along a line too
+    Collinear(usize, usize),
+    Single(usize),
+    Empty,
+}
+
+/// Finds the cheapest presses by enumerating the presses of every button
+/// outside the basis, and solving the basis for what is left of the target.
+/// Buttons that do not move the claw are never pressed.
+struct Solver<'a> {
+    buttons: &'a [Button],
+    basis: Basis,
+    enumerated: Vec<usize>,
 }
 
-impl PositiveDiophantineEquation {
-    fn intersect(&self, other: &PositiveDiophantineEquation) -> Option<Position> {
-        // Does NOT handle all cases (e.g. gcd == 0), but works for the given input ¯\_(ツ)_/¯
-        let gcd = bezout(self.a, other.a).0;
-        let lambda = (self.a * other.a) / gcd;
+impl<'a> Solver<'a> {
+    fn new(buttons: &'a [Button]) -> Self {
+        let moving: Vec<usize> = (0..buttons.len())
+            .filter(|&idx| buttons[idx].movement != Move::new(0, 0))
+            .collect();
+        let independent = moving.iter().enumerate().find_map(|(position, &first)| {
+            moving[position + 1..]
+                .iter()
+                .find(|&&second| determinant(&buttons[first], &buttons[second]) != 0)
+                .map(|&second| (first, second))
+        });
+
+        let basis = match (independent, moving.as_slice()) {
+            (Some((first, second)), _) => Basis::Independent(first, second),
+            (None, [first, second, ..]) => Basis::Collinear(*first, *second),
+            (None, [first]) => Basis::Single(*first),
+            (None, []) => Basis::Empty,
+        };
+        let in_basis = |idx: &usize| match basis {
+            Basis::Independent(first, second) | Basis::Collinear(first, second) => {
+                *idx == first || *idx == second
+            }
+            Basis::Single(first) => *idx == first,
+            Basis::Empty => false,
+        };
+        let enumerated = moving
+            .iter()
+            .copied()
+            .filter(|idx| !in_basis(idx))
+            .collect();
+
+        Self {
+            buttons,
+            basis,
+            enumerated,
+        }
+    }
+
+    fn solve(&self, target: Position) -> Option<Vec<usize>> {
+        let mut counts = vec![0; self.buttons.len()];
+        let mut best = None;
+        self.search(0, target, &mut counts, &mut best);
 
-        let denominator =
-            ((lambda * self.b) / self.a) as isize - ((lambda * other.b) / other.a) as isize;
-        if denominator == 0 {
-            return None;
+        best.map(|(_, counts)| counts)
+    }
+
+    fn search(
+        &self,
+        depth: usize,
+        remaining: Position,
+        counts: &mut Vec<usize>,
+        best: &mut Option<(usize, Vec<usize>)>,
+    ) {
+        let Some(&idx) = self.enumerated.get(depth) else {
+            if let Some(solution) = self.solve_basis(remaining, counts) {
+                let tokens = self.tokens(&solution);
+                if best
+                    .as_ref()
+                    .is_none_or(|(best_tokens, _)| tokens < *best_tokens)
+                {
+                    *best = Some((tokens, solution));
+                }
+            }
+            return;
+        };
+
+        let button = &self.buttons[idx];
+        let most_presses = [
+            button.limit,
+            remaining.x.checked_div(button.movement.x),
+            remaining.y.checked_div(button.movement.y),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap();
+
+        for presses in 0..=most_presses {
+            counts[idx] = presses;
+            self.search(
+                depth + 1,
+                remaining - button.movement * presses,
+                counts,
+                best,
+            );
         }
+        counts[idx] = 0;
+    }
 
-        let numerator =
-            ((lambda * self.c) / self.a) as isize - ((lambda * other.c) / other.a) as isize;
-        let mu = self.b * self.a / bezout(self.a, self.b).0;
+    fn solve_basis(&self, target: Position, counts: &[usize]) -> Option<Vec<usize>> {
+        let mut solution = counts.to_vec();
 
-        let y = numerator / denominator;
-        let x = ((self.c * mu) as isize - (mu * self.b) as isize * y) / (mu * self.a) as isize;
+        match self.basis {
+            Basis::Independent(first, second) => {
+                // Cramer's rule
+                let (a, b) = (&self.buttons[first], &self.buttons[second]);
+                let denominator = determinant(a, b);
+                let (tx, ty) = (target.x as i128, target.y as i128);
+                let first_numerator = tx * b.movement.y as i128 - b.movement.x as i128 * ty;
+                let second_numerator = a.movement.x as i128 * ty - tx * a.movement.y as i128;
+                if first_numerator % denominator != 0 || second_numerator % denominator != 0 {
+                    return None;
+                }
 
-        // Last validation in case some integer division arithmetic went wrong
-        Point2D::new(x, y).cast().ok().filter(|candidate| {
-            self.a * candidate.x + self.b * candidate.y == self.c
-                && other.a * candidate.x + other.b * candidate.y == other.c
-        })
+                solution[first] = presses_within_limit(a, first_numerator / denominator)?;
+                solution[second] = presses_within_limit(b, second_numerator / denominator)?;
+            }
+            Basis::Collinear(first, second) => {
+                let (a, b) = (&self.buttons[first], &self.buttons[second]);
+                let (first_presses, second_presses) = solve_collinear(a, b, target)?;
+                solution[first] = first_presses;
+                solution[second] = second_presses;
+            }
+            Basis::Single(first) => {
+                let button = &self.buttons[first];
+                let presses = target
+                    .x
+                    .checked_div(button.movement.x)
+                    .or(target.y.checked_div(button.movement.y))?;
+                if button.movement * presses != target {
+                    return None;
+                }
+
+                solution[first] = presses_within_limit(button, presses as i128)?;
+            }
+            Basis::Empty => {
+                if target != Position::new(0, 0) {
+                    return None;
+                }
+            }
+        }
+
+        Some(solution)
+    }
+
+    fn tokens(&self, counts: &[usize]) -> usize {
+        counts
+            .iter()
+            .zip(self.buttons.iter())
+            .map(|(count, button)| count * button.cost)
+            .sum()
     }
 }
 
+fn determinant(first: &Button, second: &Button) -> i128 {
+    first.movement.x as i128 * second.movement.y as i128
+        - second.movement.x as i128 * first.movement.y as i128
+}
+
+fn presses_within_limit(button: &Button, presses: i128) -> Option<usize> {
+    let presses = usize::try_from(presses).ok()?;
+
+    match button.limit {
+        Some(limit) if presses > limit => None,
+        _ => Some(presses),
+    }
+}
+
+/// Cheapest presses for two buttons moving along the same line. Both moves
+/// are multiples of the smallest step along the line, so the problem becomes
+/// α * a + β * b = c in steps. Its solutions are a line too, where the cost
+/// changes linearly, so the cheapest one is at either end of the range
+/// allowed by the limits.
+fn solve_collinear(first: &Button, second: &Button, target: Position) -> Option<(usize, usize)> {
+    let step_size = bezout(first.movement.x, first.movement.y).0;
+    let step = Move::new(first.movement.x / step_size, first.movement.y / step_size);
+    let steps = |movement: Position| (movement.x + movement.y) / (step.x + step.y);
+
+    let target_steps = steps(target);
+    if step * target_steps != target {
+        return None;
+    }
+
+    let (a, b, c) = (step_size, steps(second.movement), target_steps);
+    let (gcd, s, t) = bezout(a, b);
+    if c % gcd != 0 {
+        return None;
+    }
+
+    // α = α₀ + k * b', β = β₀ - k * a'
+    let (a_step, b_step) = ((a / gcd) as i128, (b / gcd) as i128);
+    let alpha_0 = s as i128 * (c / gcd) as i128;
+    let beta_0 = t as i128 * (c / gcd) as i128;
+
+    let mut lowest_k = div_ceil(-alpha_0, b_step);
+    let mut highest_k = (beta_0).div_euclid(a_step);
+    if let Some(limit) = first.limit {
+        highest_k = highest_k.min((limit as i128 - alpha_0).div_euclid(b_step));
+    }
+    if let Some(limit) = second.limit {
+        lowest_k = lowest_k.max(div_ceil(beta_0 - limit as i128, a_step));
+    }
+    if lowest_k > highest_k {
+        return None;
+    }
+
+    let cost_slope = first.cost as i128 * b_step - second.cost as i128 * a_step;
+    let k = if cost_slope > 0 { lowest_k } else { highest_k };
+
+    Some((
+        (alpha_0 + k * b_step) as usize,
+        (beta_0 - k * a_step) as usize,
+    ))
+}
+
+fn div_ceil(numerator: i128, denominator: i128) -> i128 {
+    -(-numerator).div_euclid(denominator)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::Rng;
+    use std::fs;
+
+    fn machine(buttons: &[((usize, usize), usize)], prize: (usize, usize)) -> Machine {
+        Machine {
+            buttons: buttons
+                .iter()
+                .map(|&((x, y), cost)| Button::new(Move::new(x, y), cost))
+                .collect(),
+            prize: Position::new(prize.0, prize.1),
+        }
+    }
 
     #[test]
-    fn intersect() {
+    fn test_required_tokens() {
+        let input = fs::read_to_string("data/day13/test_input").unwrap();
+        let machines: Vec<Machine> = parse_input(&input)
+            .into_iter()
+            .map(|machine| machine.with_press_limit(100))
+            .collect();
+        let tokens: Vec<_> = machines
+            .iter()
+            .map(|machine| machine.required_tokens(0))
+            .collect();
+
+        assert_eq!(vec![Some(280), None, Some(200), None], tokens);
+
+        let machines = parse_input(&input);
+        let tokens: Vec<_> = machines
+            .iter()
+            .map(|machine| machine.required_tokens(10000000000000))
+            .collect();
+
+        assert_eq!(None, tokens[0]);
+        assert_eq!(None, tokens[2]);
+        assert_eq!(875318608908, tokens.into_iter().flatten().sum::<usize>());
+    }
+
+    #[test]
+    fn test_two_buttons() {
         let cases = [
             ((1, 1, 0), (1, 1, 1), None),
-            ((1, 1, 1), (2, 2, 2), None), // Not enough information
             ((3, 1, 5), (3, 2, 6), None), // x = 4/3
-            ((1, 1, 6), (3, 2, 12), Some(Position::new(0, 6))),
-            ((69, 41, 5242), (48, 88, 3944), Some(Position::new(73, 5))),
-            ((20, 61, 4553), (52, 42, 5658), Some(Position::new(66, 53))),
-            ((20, 61, 4553), (52, 42, 5658), Some(Position::new(66, 53))),
-            ((21, 26, 690), (16, 68, 1104), Some(Position::new(18, 12))),
+            ((1, 1, 6), (3, 2, 12), Some(vec![0, 6])),
+            ((69, 41, 5242), (48, 88, 3944), Some(vec![73, 5])),
+            ((20, 61, 4553), (52, 42, 5658), Some(vec![66, 53])),
+            ((21, 26, 690), (16, 68, 1104), Some(vec![18, 12])),
+            // Both buttons move the same way, B is cheaper
+            ((1, 1, 1), (2, 2, 2), Some(vec![0, 1])),
         ];
 
-        for (eq_a, eq_b, expected) in cases {
-            let result = PositiveDiophantineEquation {
-                a: eq_a.0,
-                b: eq_a.1,
-                c: eq_a.2,
+        for (eq_x, eq_y, expected) in cases {
+            let machine = machine(
+                &[((eq_x.0, eq_y.0), 3), ((eq_x.1, eq_y.1), 1)],
+                (eq_x.2, eq_y.2),
+            );
+            let presses = machine.cheapest_presses(0).map(|presses| presses.counts);
+            assert_eq!(expected, presses);
+        }
+    }
+
+    #[test]
+    fn test_collinear_buttons() {
+        let machine = machine(&[((2, 2), 3), ((3, 3), 1)], (12, 12));
+        assert_eq!(
+            Some(Presses {
+                counts: vec![0, 4],
+                tokens: 4
+            }),
+            machine.cheapest_presses(0)
+        );
+
+        let mut limited = machine.clone();
+        limited.buttons[1].limit = Some(2);
+        assert_eq!(
+            Some(vec![3, 2]),
+            limited.cheapest_presses(0).map(|p| p.counts)
+        );
+
+        let expensive = machine.with_costs(&[1, 10]);
+        assert_eq!(Some(6), expensive.required_tokens(0));
+        assert_eq!(Some(15), expensive.required_tokens(1));
+        assert_eq!(Some(7), expensive.required_tokens(2));
+    }
+
+    #[test]
+    fn test_more_buttons() {
+        let machine = machine(&[((1, 0), 1), ((0, 1), 1), ((1, 1), 1)], (5, 3));
+        assert_eq!(
+            Some(Presses {
+                counts: vec![2, 0, 3],
+                tokens: 5
+            }),
+            machine.cheapest_presses(0)
+        );
+
+        let mut limited = machine.clone();
+        limited.buttons[2].limit = Some(1);
+        assert_eq!(
+            Some(vec![4, 2, 1]),
+            limited.cheapest_presses(0).map(|p| p.counts)
+        );
+
+        let stuck = Machine {
+            buttons: vec![Button::new(Move::new(0, 0), 0)],
+            prize: Position::new(1, 0),
+        };
+        assert_eq!(None, stuck.required_tokens(0));
+        let already_there = Machine {
+            prize: Position::new(0, 0),
+            ..stuck
+        };
+        assert_eq!(
+            Some(Presses {
+                counts: vec![0],
+                tokens: 0
+            }),
+            already_there.cheapest_presses(0)
+        );
+    }
+
+    #[test]
+    fn test_brute_force() {
+        // Parallel buttons, where the longer one is cheaper per step until
+        // it runs out of presses
+        let mut parallel = Machine {
+            buttons: vec![
+                Button::new(Move::new(1, 1), 3),
+                Button::new(Move::new(2, 2), 4),
+            ],
+            prize: Position::new(3, 3),
+        };
+        assert_eq!(Some(7), parallel.required_tokens(0));
+        parallel.buttons[1].limit = Some(0);
+        assert_eq!(Some(9), parallel.required_tokens(0));
+
+        let mut rng = Rng::new(13);
+        let mut next = move |bound: u64| rng.below(bound) as usize;
+
+        for _ in 0..300 {
+            let buttons: Vec<Button> = (0..1 + next(3))
+                .map(|_| {
+                    let mut button = Button::new(Move::new(next(4), next(4)), next(5));
+                    button.limit = Some(next(6)).filter(|_| next(2) == 0);
+                    button
+                })
+                .collect();
+            let machine = Machine {
+                buttons,
+                prize: Position::new(next(13), next(13)),
+            };
+
+            // Every combination of up to 12 presses per button
+            let mut cheapest: Option<usize> = None;
+            let combinations = 13usize.pow(machine.buttons.len() as u32);
+            for combination in 0..combinations {
+                let counts: Vec<usize> = (0..machine.buttons.len())
+                    .map(|idx| combination / 13usize.pow(idx as u32) % 13)
+                    .collect();
+                let within_limits = counts
+                    .iter()
+                    .zip(machine.buttons.iter())
+                    .all(|(&count, button)| button.limit.is_none_or(|limit| count <= limit));
+                let position = counts
+                    .iter()
+                    .zip(machine.buttons.iter())
+                    .fold(Position::new(0, 0), |position, (&count, button)| {
+                        position + button.movement * count
+                    });
+                if within_limits && position == machine.prize {
+                    let tokens = counts
+                        .iter()
+                        .zip(machine.buttons.iter())
+                        .map(|(count, button)| count * button.cost)
+                        .sum();
+                    cheapest = Some(cheapest.map_or(tokens, |best| best.min(tokens)));
+                }
+            }
+
+            let presses = machine.cheapest_presses(0);
+            assert_eq!(
+                cheapest,
+                presses.as_ref().map(|presses| presses.tokens),
+                "{:?}",
+                machine
+            );
+            if let Some(presses) = presses {
+                let position = presses
+                    .counts
+                    .iter()
+                    .zip(machine.buttons.iter())
+                    .fold(Position::new(0, 0), |position, (&count, button)| {
+                        position + button.movement * count
+                    });
+                assert_eq!(machine.prize, position);
             }
-            .intersect(&PositiveDiophantineEquation {
-                a: eq_b.0,
-                b: eq_b.1,
-                c: eq_b.2,
-            });
-            assert_eq!(result, expected);
         }
     }
 }
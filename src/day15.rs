@@ -1,6 +1,7 @@
 use std::collections::HashSet;
 use std::str::FromStr;
 
+use crate::obstacles::{NextObstacle, ObstacleIndex};
 use crate::spatial::{Direction, Point2D};
 
 pub fn parse_input(input: &str) -> (Warehouse, Vec<Move>) {
@@ -24,7 +25,7 @@ pub struct Warehouse {
     pub width: usize,
     pub height: usize,
     pub robot: Position,
-    pub walls: HashSet<Position>,
+    pub walls: ObstacleIndex<Coordinate>,
     pub boxes: HashSet<Position>,
 }
 pub type Move = Direction;
@@ -44,13 +45,14 @@ impl Warehouse {
 
     fn apply_move(&mut self, move_: Move) {
         let delta = Position::from(move_);
+        let wall = self.walls.next_obstacle(self.robot, move_);
         let mut current = self.robot + delta;
 
-        while self.is_valid(current) && self.boxes.contains(&current) {
+        while Some(current) != wall && self.is_valid(current) && self.boxes.contains(&current) {
             current = current + delta;
         }
 
-        if !self.is_valid(current) || self.walls.contains(&current) {
+        if !self.is_valid(current) || Some(current) == wall {
             // we bumped into a wall, so we don't move
             return;
         }
@@ -80,7 +82,7 @@ impl FromStr for Warehouse {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut walls = HashSet::new();
+        let mut walls = ObstacleIndex::new();
         let mut boxes = HashSet::new();
         let mut robot = None;
         let width = s.find('\n').unwrap() - 2;
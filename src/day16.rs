@@ -1,21 +1,63 @@
 use std::collections::HashSet;
+use std::fmt;
 use std::hash::Hash;
 use std::str::FromStr;
 
-use crate::graph::{Dijkstra, WeightedGraph};
+use crate::graph::{Dijkstra, DijkstraTraversal, WeightedGraph};
 use crate::parse::TextGrid;
 use crate::spatial::{Direction, Orientation, Point2D};
 
+/// A maze of any rectangular size. Reindeers may start on any of the S tiles
+/// and the race ends on any of the E tiles.
 pub struct Maze {
     content: TextGrid,
-    start: Position,
-    end: Position,
+    starts: Vec<Position>,
+    ends: HashSet<Position>,
+    start_facing: Direction,
+    costs: Costs,
 }
 
-#[derive(Clone, Copy, Eq, Hash, PartialEq)]
-struct Reindeer {
-    position: Position,
-    facing: Direction,
+/// Score added by each kind of move. Both should be positive, otherwise
+/// there may be endless optimal routes going round in circles.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Costs {
+    pub step: Score,
+    pub turn: Score,
+}
+
+impl Default for Costs {
+    fn default() -> Self {
+        Self {
+            step: 1,
+            turn: 1000,
+        }
+    }
+}
+
+/// The state of a reindeer along a route. Rows grow downwards in the maze,
+/// so Direction::Up means facing south.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Reindeer {
+    pub position: Position,
+    pub facing: Direction,
+}
+
+/// A route compressed into moves, as in "forward 5, turn CW, forward 3".
+/// Turns are clockwise or counterclockwise as seen on the printed maze.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Instruction {
+    Forward(usize),
+    Turn(Orientation),
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Forward(steps) => write!(f, "forward {}", steps),
+            Instruction::Turn(Orientation::Clockwise) => write!(f, "turn CW"),
+            Instruction::Turn(Orientation::Counterclockwise) => write!(f, "turn CCW"),
+        }
+    }
 }
 
 type Position = Point2D<Coordinate>;
@@ -23,21 +65,29 @@ type Coordinate = usize;
 type Score = u64;
 
 impl Maze {
+    /// Changes the direction reindeers face at the start, which is east
+    /// (Direction::Right) by default.
+    pub fn with_start_facing(self, start_facing: Direction) -> Self {
+        Self {
+            start_facing,
+            ..self
+        }
+    }
+
+    pub fn with_costs(self, costs: Costs) -> Self {
+        Self { costs, ..self }
+    }
+
     pub fn shortest_paths_tiles(&self) -> usize {
-        let initial_reindeer = Reindeer {
-            position: self.start,
-            facing: Direction::Right,
-        };
-        let is_end = |reindeer: &Reindeer| reindeer.position == self.end;
-        let traversal = self.traverse(&[initial_reindeer], is_end);
+        let traversal = self.traverse_to_end();
 
-        // Find all the nodes in the graph that correspond to the end position
+        // Find all the nodes in the graph that correspond to an end position
         // and have the minimum score
-        let min_score: Score = traversal.shortest_distance(is_end).unwrap();
+        let min_score: Score = traversal.shortest_distance(|r| self.is_end(r)).unwrap();
         let goals = traversal
             .distances
             .iter()
-            .filter(|(reindeer, &score)| is_end(reindeer) && score == min_score)
+            .filter(|(reindeer, &score)| self.is_end(reindeer) && score == min_score)
             .map(|(reindeer, _)| reindeer);
 
         // For each of those, find all the shortest paths that lead to it
@@ -47,7 +97,7 @@ impl Maze {
                 traversal
                     .shortest_paths(end)
                     .into_iter()
-                    .flat_map(|path| path)
+                    .flatten()
                     .map(|reindeer| reindeer.position)
             })
             .collect();
@@ -56,15 +106,107 @@ impl Maze {
     }
 
     pub fn lowest_score(&self) -> Score {
-        let initial_reindeer = Reindeer {
-            position: self.start,
-            facing: Direction::Right,
+        self.traverse_to_end()
+            .shortest_distance(|reindeer| self.is_end(reindeer))
+            .unwrap()
+    }
+
+    /// One of the routes with the lowest score, from start to end
+    pub fn optimal_route(&self) -> Option<Vec<Reindeer>> {
+        self.optimal_routes(1).pop()
+    }
+
+    /// Up to the given number of distinct routes with the lowest score
+    pub fn optimal_routes(&self, limit: usize) -> Vec<Vec<Reindeer>> {
+        let traversal = self.traverse_to_end();
+        let Some(min_score) = traversal.shortest_distance(|r| self.is_end(r)) else {
+            return vec![];
         };
-        let is_end = |reindeer: &Reindeer| reindeer.position == self.end;
+        let mut pending: Vec<Vec<Reindeer>> = traversal
+            .distances
+            .iter()
+            .filter(|(reindeer, &score)| self.is_end(reindeer) && score == min_score)
+            .map(|(&reindeer, _)| vec![reindeer])
+            .collect();
+        let mut routes = vec![];
+
+        // Same as DijkstraTraversal::shortest_paths, but depth first so it
+        // can stop early instead of listing every route
+        while let Some(mut route) = pending.pop() {
+            if routes.len() == limit {
+                break;
+            }
+
+            let current = route.last().unwrap();
+            if traversal.seeds.contains(current) {
+                route.reverse();
+                routes.push(route);
+                continue;
+            }
+
+            for predecessor in traversal.predecessors[current].iter() {
+                let mut new_route = route.clone();
+                new_route.push(*predecessor);
+                pending.push(new_route);
+            }
+        }
+
+        routes
+    }
+
+    /// The maze with every tile on any of the routes marked with an O
+    pub fn render_routes(&self, routes: &[Vec<Reindeer>]) -> String {
+        let tiles: HashSet<Position> = routes
+            .iter()
+            .flatten()
+            .map(|reindeer| reindeer.position)
+            .collect();
+        let mut rendered = String::new();
 
-        let traversal = self.traverse(&[initial_reindeer], is_end);
+        for y in 0..self.content.height {
+            for x in 0..self.content.width {
+                let position = Position::new(x, y);
+                match tiles.contains(&position) {
+                    true => rendered.push('O'),
+                    false => rendered.push(self.content.char_at(position).unwrap()),
+                }
+            }
+            rendered.push('\n');
+        }
 
-        traversal.shortest_distance(is_end).unwrap()
+        rendered
+    }
+
+    fn traverse_to_end(&self) -> DijkstraTraversal<Reindeer, Score> {
+        let initial_reindeers: Vec<_> = self
+            .starts
+            .iter()
+            .map(|&position| Reindeer {
+                position,
+                facing: self.start_facing,
+            })
+            .collect();
+
+        self.traverse(&initial_reindeers, |reindeer| self.is_end(reindeer))
+    }
+
+    fn is_end(&self, reindeer: &Reindeer) -> bool {
+        self.ends.contains(&reindeer.position)
+    }
+
+    /// The position one step ahead, unless it is a wall or out of the maze
+    fn step(&self, position: Position, facing: Direction) -> Option<Position> {
+        // Coordinates are unsigned, so stepping left of 0 must not happen
+        let leaves_maze = match facing {
+            Direction::Left => position.x == 0,
+            Direction::Down => position.y == 0,
+            Direction::Right | Direction::Up => false,
+        };
+
+        Some(position)
+            .filter(|_| !leaves_maze)
+            .map(|position| position.advance(facing))
+            .filter(|&position| self.is_valid(position) && self.is_empty(position))
     }
 
     fn is_empty(&self, position: Position) -> bool {
@@ -75,20 +217,43 @@ impl Maze {
     }
 
     fn is_valid(&self, position: Position) -> bool {
-        position.x > 0
-            && position.x < self.content.width - 1
-            && position.y > 0
-            && position.y < self.content.width - 1
+        position.x < self.content.width && position.y < self.content.height
+    }
+}
+
+/// Compresses a route into moves, merging consecutive steps forward
+pub fn instructions(route: &[Reindeer]) -> Vec<Instruction> {
+    let mut instructions = vec![];
+
+    for pair in route.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let instruction = if from.facing == to.facing {
+            Instruction::Forward(1)
+        } else if from.facing.rotate(Orientation::Clockwise) == to.facing {
+            // Rotating in the y-up convention of Direction looks the other way
+            // round in the maze, where rows grow downwards
+            Instruction::Turn(Orientation::Counterclockwise)
+        } else {
+            Instruction::Turn(Orientation::Clockwise)
+        };
+
+        match (instructions.last_mut(), instruction) {
+            (Some(Instruction::Forward(steps)), Instruction::Forward(more)) => *steps += more,
+            _ => instructions.push(instruction),
+        }
     }
+
+    instructions
 }
 
 impl WeightedGraph<Reindeer, Score> for Maze {
     fn neighbours(&self, reindeer: &Reindeer) -> Vec<(Score, Reindeer)> {
-        let forward = Some(Reindeer {
-            position: reindeer.position.advance(reindeer.facing),
-            facing: reindeer.facing,
-        })
-        .filter(|candidate| self.is_valid(candidate.position) && self.is_empty(candidate.position));
+        let forward = self
+            .step(reindeer.position, reindeer.facing)
+            .map(|position| Reindeer {
+                position,
+                facing: reindeer.facing,
+            });
         let turn_cw = Reindeer {
             position: reindeer.position,
             facing: reindeer.facing.rotate(Orientation::Clockwise),
@@ -98,10 +263,11 @@ impl WeightedGraph<Reindeer, Score> for Maze {
             facing: reindeer.facing.rotate(Orientation::Counterclockwise),
         };
 
-        let forward_candidate = forward.map(|forward_candidate| (1, forward_candidate));
+        let forward_candidate =
+            forward.map(|forward_candidate| (self.costs.step, forward_candidate));
         let turn_candidates = [turn_cw, turn_ccw]
             .into_iter()
-            .map(|turn_candidate| (1000, turn_candidate));
+            .map(|turn_candidate| (self.costs.turn, turn_candidate));
 
         forward_candidate
             .into_iter()
@@ -115,13 +281,125 @@ impl FromStr for Maze {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let content: TextGrid = s.parse()?;
-        let start = content.iter().find(|&(_, c)| c == 'S').unwrap().0;
-        let end = content.iter().find(|&(_, c)| c == 'E').unwrap().0;
+        let tiles = |tile: char| {
+            content
+                .iter()
+                .filter(move |&(_, c)| c == tile)
+                .map(|(p, _)| p)
+        };
+        let starts: Vec<_> = tiles('S').collect();
+        let ends: HashSet<_> = tiles('E').collect();
+
+        if starts.is_empty() || ends.is_empty() {
+            return Err(());
+        }
 
         Ok(Self {
             content,
-            start,
-            end,
+            starts,
+            ends,
+            start_facing: Direction::Right,
+            costs: Costs::default(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_lowest_score() {
+        let input = fs::read_to_string("data/day16/test_input").unwrap();
+        let maze: Maze = input.parse().unwrap();
+        assert_eq!(7036, maze.lowest_score());
+        assert_eq!(45, maze.shortest_paths_tiles());
+
+        let input = fs::read_to_string("data/day16/test_input_2").unwrap();
+        let maze: Maze = input.parse().unwrap();
+        assert_eq!(11048, maze.lowest_score());
+        assert_eq!(64, maze.shortest_paths_tiles());
+    }
+
+    #[test]
+    fn test_optimal_routes() {
+        let input = fs::read_to_string("data/day16/test_input").unwrap();
+        let maze: Maze = input.parse().unwrap();
+        let routes = maze.optimal_routes(100);
+        let route = maze.optimal_route().unwrap();
+
+        assert_eq!(3, routes.len());
+        assert!(routes.contains(&route));
+        assert_eq!(45, maze.render_routes(&routes).matches('O').count());
+        assert_eq!(2, maze.optimal_routes(2).len());
+
+        // Each turn costs 1000 and each step forward 1
+        let score = |route: &[Reindeer]| {
+            instructions(route)
+                .into_iter()
+                .map(|instruction| match instruction {
+                    Instruction::Forward(steps) => steps as Score,
+                    Instruction::Turn(_) => 1000,
+                })
+                .sum::<Score>()
+        };
+        assert!(routes.iter().all(|route| score(route) == 7036));
+    }
+
+    #[test]
+    fn test_instructions() {
+        let maze: Maze = "#####\n#..E#\n#.###\n#S..#\n#####\n".parse().unwrap();
+        let route = maze.optimal_route().unwrap();
+        let instructions: Vec<_> = instructions(&route)
+            .into_iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+
+        // Facing east at the start, going north is a counterclockwise turn
+        assert_eq!(
+            vec!["turn CCW", "forward 2", "turn CW", "forward 2"],
+            instructions
+        );
+        assert_eq!(
+            "#####\n#OOO#\n#O###\n#O..#\n#####\n",
+            maze.render_routes(&[route])
+        );
+    }
+
+    #[test]
+    fn test_non_square_maze() {
+        // No outer walls, and wider than tall
+        let maze: Maze = "S.#....E\n..#.##..\n........\n".parse().unwrap();
+        assert_eq!(11 + 3000, maze.lowest_score());
+        // Turning down right at the start or one step later are both optimal
+        assert_eq!(14, maze.shortest_paths_tiles());
+
+        // Taller than wide
+        let maze: Maze = "S.\n#.\n#.\n.E\n".parse().unwrap();
+        assert_eq!(4 + 1000, maze.lowest_score());
+    }
+
+    #[test]
+    fn test_several_starts_and_ends() {
+        let maze: Maze = "S...E\n.###.\nS...E\n".parse().unwrap();
+        assert_eq!(4, maze.lowest_score());
+        assert_eq!(10, maze.shortest_paths_tiles());
+
+        assert!("S...\n....\n".parse::<Maze>().is_err());
+    }
+
+    #[test]
+    fn test_facing_and_costs() {
+        let maze: Maze = "E...S\n".parse().unwrap();
+        assert_eq!(2004, maze.lowest_score());
+
+        let maze = maze.with_start_facing(Direction::Left);
+        assert_eq!(4, maze.lowest_score());
+
+        let maze = maze
+            .with_start_facing(Direction::Right)
+            .with_costs(Costs { step: 3, turn: 5 });
+        assert_eq!(10 + 12, maze.lowest_score());
+    }
+}
@@ -1,9 +1,7 @@
-use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use crate::common::{binary_search, Match};
-use crate::graph::Dijkstra;
 use crate::graph::WeightedGraph;
+use crate::graph::{Dijkstra, DisjointSet};
 use crate::spatial::{Point2D, Point2DCast};
 
 type BytePosition = Point2D<Coordinate>;
@@ -32,30 +30,64 @@ impl Grid {
     pub fn shortest_distance(&self) -> usize {
         let origin = BytePosition::new(0, 0);
         let target = BytePosition::new(self.width - 1, self.height - 1);
-        let result = self.traverse(&vec![origin], |&node| node == target);
+        let result = self.traverse(&[origin], |&node| node == target);
 
         result.distances[&target]
     }
 
     // Kinda funky API but oh well
     pub fn first_blocking_byte(&self, bytes: &[BytePosition]) -> BytePosition {
-        let target = self.target();
-        let result = binary_search(0, bytes.len(), |candidate| {
-            let grid = Grid::new(self.width, self.height, &bytes[..candidate]);
-            let result = grid.traverse(&[self.origin()], |&node| node == target);
-            let is_reachable = result.distances.contains_key(&target);
-
-            // bool -> Ordering mapping here is arbitrary, we are only interested in changes
-            if is_reachable {
-                return Ordering::Less;
-            } else {
-                return Ordering::Greater;
+        self.drop_analysis(bytes)
+            .first_blocking_byte
+            .expect("not found")
+            .1
+    }
+
+    /// Replays the byte drops backwards, starting from the grid with every
+    /// byte fallen and freeing them one at a time. Connectivity only ever
+    /// grows that way, so a disjoint set can keep track of it.
+    pub fn drop_analysis(&self, bytes: &[BytePosition]) -> DropAnalysis {
+        // Only the first drop on a cell matters, later ones land on a byte
+        let mut fallen_at = HashMap::new();
+        for (idx, byte) in bytes.iter().enumerate().rev() {
+            fallen_at.insert(*byte, idx);
+        }
+
+        let mut connectivity = DropConnectivity {
+            grid: self,
+            sets: DisjointSet::new(self.width * self.height),
+            members: vec![vec![]; self.width * self.height],
+            free: HashSet::new(),
+            reachable_until: HashMap::new(),
+        };
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let cell = BytePosition::new(x, y);
+                if !fallen_at.contains_key(&cell) {
+                    connectivity.free(cell, bytes.len());
+                }
+            }
+        }
+
+        let mut first_blocking_byte = None;
+        let mut was_connected = connectivity.is_connected();
+        for (idx, byte) in bytes.iter().enumerate().rev() {
+            if fallen_at[byte] != idx || !self.is_valid(byte.cast().unwrap()) {
+                continue;
             }
-        });
 
-        match result {
-            Match::After(idx) => bytes[idx - 1],
-            _ => panic!("not found"),
+            connectivity.free(*byte, idx);
+            if !was_connected && connectivity.is_connected() {
+                // Going forwards, this is the byte that cut the path
+                first_blocking_byte = Some((idx, *byte));
+                was_connected = true;
+            }
+        }
+
+        DropAnalysis {
+            first_blocking_byte,
+            reachable_until: connectivity.reachable_until,
         }
     }
 
@@ -83,6 +115,103 @@ impl Grid {
     }
 }
 
+/// Result of replaying a sequence of byte drops on a grid
+pub struct DropAnalysis {
+    /// The first byte after which the target is no longer reachable, along
+    /// with its index in the drop order
+    pub first_blocking_byte: Option<(usize, BytePosition)>,
+    /// For every cell reachable from the origin at some point, the largest
+    /// number of fallen bytes it remains reachable with
+    pub reachable_until: HashMap<BytePosition, usize>,
+}
+
+/// Connected components of the free cells of a grid, growing as cells are freed
+struct DropConnectivity<'a> {
+    grid: &'a Grid,
+    sets: DisjointSet,
+    members: Vec<Vec<BytePosition>>, // only kept for the representative of each set
+    free: HashSet<BytePosition>,
+    reachable_until: HashMap<BytePosition, usize>,
+}
+
+impl DropConnectivity<'_> {
+    fn free(&mut self, cell: BytePosition, fallen: usize) {
+        if self.grid.blocks.contains(&cell) {
+            return;
+        }
+
+        self.free.insert(cell);
+        let idx = self.index(cell);
+        self.members[idx] = vec![cell];
+        if cell == self.grid.origin() {
+            self.reachable_until.insert(cell, fallen);
+        }
+
+        for (_, neighbour) in self.grid.neighbours(&cell) {
+            if self.free.contains(&neighbour) {
+                self.join(cell, neighbour, fallen);
+            }
+        }
+    }
+
+    fn join(&mut self, first: BytePosition, second: BytePosition, fallen: usize) {
+        let (first_root, second_root) = (
+            self.sets.find(self.index(first)),
+            self.sets.find(self.index(second)),
+        );
+        if first_root == second_root {
+            return;
+        }
+
+        let origin_root = self.origin_root();
+        self.sets.union(first_root, second_root);
+        let root = self.sets.find(first_root);
+        let child = if root == first_root {
+            second_root
+        } else {
+            first_root
+        };
+
+        // The cells on the side that was not connected to the origin just became reachable
+        if origin_root == Some(first_root) || origin_root == Some(second_root) {
+            let newly_reachable = if origin_root == Some(first_root) {
+                second_root
+            } else {
+                first_root
+            };
+            for cell in self.members[newly_reachable].iter() {
+                self.reachable_until.insert(*cell, fallen);
+            }
+        }
+
+        let mut child_members = std::mem::take(&mut self.members[child]);
+        if child_members.len() > self.members[root].len() {
+            std::mem::swap(&mut child_members, &mut self.members[root]);
+        }
+        self.members[root].extend(child_members);
+    }
+
+    fn origin_root(&mut self) -> Option<usize> {
+        let origin = self.grid.origin();
+        if self.free.contains(&origin) {
+            Some(self.sets.find(self.index(origin)))
+        } else {
+            None
+        }
+    }
+
+    fn is_connected(&mut self) -> bool {
+        let (origin, target) = (self.grid.origin(), self.grid.target());
+        self.free.contains(&origin)
+            && self.free.contains(&target)
+            && self.sets.connected(self.index(origin), self.index(target))
+    }
+
+    fn index(&self, cell: BytePosition) -> usize {
+        cell.y * self.grid.width + cell.x
+    }
+}
+
 impl WeightedGraph<BytePosition, Coordinate> for Grid {
     fn neighbours(&self, node: &BytePosition) -> Vec<(Coordinate, BytePosition)> {
         // UGH I hate usize bounds and having to manually cast to do anything meaningful
@@ -135,4 +264,36 @@ mod tests {
 
         assert_eq!(Point2D::new(6, 1), grid.first_blocking_byte(&bytes));
     }
+
+    #[test]
+    fn test_drop_analysis() {
+        let input = fs::read_to_string("data/day18/test_input").unwrap();
+        let bytes = parse_input(&input);
+        let grid_size = 7;
+        let grid = Grid::new(grid_size, grid_size, &[]);
+        let analysis = grid.drop_analysis(&bytes);
+
+        assert_eq!(Some((20, Point2D::new(6, 1))), analysis.first_blocking_byte);
+        assert_eq!(20, analysis.reachable_until[&grid.target()]);
+        assert_eq!(bytes.len(), analysis.reachable_until[&grid.origin()]);
+
+        // Cross-check against a full traversal after some of the bytes fell
+        for fallen in [0, 12, 20, 21, bytes.len()] {
+            let partial_grid = Grid::new(grid_size, grid_size, &bytes[..fallen]);
+            let traversal = partial_grid.traverse(&[grid.origin()], |_| false);
+
+            for y in 0..grid_size {
+                for x in 0..grid_size {
+                    let cell = BytePosition::new(x, y);
+                    let expected = traversal.distances.contains_key(&cell);
+                    let actual = analysis
+                        .reachable_until
+                        .get(&cell)
+                        .map(|&until| fallen <= until)
+                        .unwrap_or(false);
+                    assert_eq!(expected, actual, "{:?} after {} bytes", cell, fallen);
+                }
+            }
+        }
+    }
 }
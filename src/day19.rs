@@ -1,5 +1,5 @@
-pub type Pattern<'a> = &'a str;
-pub type Design<'a> = &'a str;
+use crate::common::Automaton;
+use std::collections::HashSet;
 
 pub fn parse_input(input: &str) -> (Vec<String>, Vec<String>) {
     let (patterns_block, designs_block) = input.split_once("\n\n").unwrap();
@@ -9,16 +9,272 @@ pub fn parse_input(input: &str) -> (Vec<String>, Vec<String>) {
     (patterns, designs)
 }
 
-pub fn find_first_prefix(partial_design: Design, patterns: &[Pattern]) -> Option<usize> {
-    if patterns.is_empty() {
-        return None;
+/// Arranges designs with an Aho-Corasick automaton over the towel patterns,
+/// so a design is read a single time, one stripe after another, with every
+/// pattern ending at each stripe found along the way. Patterns and designs are plain bytes, so they
+/// can come from strings as well as from any byte stream.
+pub struct TowelMatcher {
+    patterns: Vec<Vec<u8>>,
+    automaton: Automaton<u8>,
+    longest_pattern: usize,
+}
+
+impl TowelMatcher {
+    /// Empty patterns are ignored, and repeated ones are only kept once
+    pub fn new<I, P>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: IntoIterator<Item = u8>,
+    {
+        let mut seen = HashSet::new();
+        let patterns: Vec<Vec<u8>> = patterns
+            .into_iter()
+            .map(|pattern| pattern.into_iter().collect())
+            .filter(|pattern: &Vec<u8>| !pattern.is_empty() && seen.insert(pattern.clone()))
+            .collect();
+        let automaton = Automaton::new(patterns.iter().map(|pattern| pattern.iter().copied()));
+        let longest_pattern = patterns.iter().map(Vec::len).max().unwrap_or(0);
+
+        Self {
+            patterns,
+            automaton,
+            longest_pattern,
+        }
     }
 
-    patterns
-        .iter()
-        .enumerate()
-        .find(|(_, pattern)| partial_design.starts_with(*pattern))
-        .map(|(idx, _)| idx)
+    pub fn is_possible<D: IntoIterator<Item = u8>>(&self, design: D) -> bool {
+        self.fold_prefixes(design, true, false, |possible, previous| {
+            possible || previous
+        })
+    }
+
+    /// Number of different ways of putting patterns together into the
+    /// design, or nothing if there are too many for a u64. A prefix with
+    /// too many only makes that so for the prefixes built on top of it, so
+    /// a design is never reported as too much by a prefix it does not use.
+    pub fn arrangements<D: IntoIterator<Item = u8>>(&self, design: D) -> Option<u64> {
+        self.fold_prefixes(design, Some(1), Some(0), |arrangements, previous| {
+            arrangements?.checked_add(previous?)
+        })
+    }
+
+    /// Some patterns making up the design, in order
+    pub fn arrangement<D: IntoIterator<Item = u8>>(&self, design: D) -> Option<Vec<&[u8]>> {
+        let last_patterns = self.last_patterns(design);
+
+        let mut arrangement = vec![];
+        let mut end = last_patterns.len() - 1;
+        while end > 0 {
+            let pattern = &self.patterns[last_patterns[end]?];
+            arrangement.push(pattern.as_slice());
+            end -= pattern.len();
+        }
+        arrangement.reverse();
+
+        Some(arrangement)
+    }
+
+    /// Whether the pattern can be made of other, strictly shorter patterns.
+    /// Only the pattern itself matches the whole of it, so it is enough to
+    /// find some possible prefix followed by a pattern that ends it.
+    fn is_splittable(&self, pattern: &[u8]) -> bool {
+        let is_possible = self.possible_prefixes(pattern);
+
+        (1..pattern.len()).any(|split| is_possible[split] && self.contains(&pattern[split..]))
+    }
+
+    fn contains(&self, pattern: &[u8]) -> bool {
+        let mut node = 0;
+        for stripe in pattern {
+            match self.automaton.child(node, *stripe) {
+                Some(child) => node = child,
+                None => return false,
+            }
+        }
+
+        self.automaton
+            .outputs(node)
+            .first()
+            .is_some_and(|&idx| self.patterns[idx].len() == pattern.len())
+    }
+
+    /// Whether each prefix of the design can be made of patterns, from the
+    /// empty one to the whole design
+    fn possible_prefixes(&self, design: &[u8]) -> Vec<bool> {
+        self.last_patterns(design.iter().copied())
+            .into_iter()
+            .enumerate()
+            .map(|(end, last_pattern)| end == 0 || last_pattern.is_some())
+            .collect()
+    }
+
+    /// Last pattern of some arrangement of each prefix of the design, from
+    /// the empty one, which needs none, to the whole design
+    fn last_patterns<D: IntoIterator<Item = u8>>(&self, design: D) -> Vec<Option<usize>> {
+        let mut last_patterns = vec![None];
+
+        self.scan(design, |end, matches| {
+            let last_pattern = matches.iter().copied().find(|&pattern| {
+                let start = end - self.patterns[pattern].len();
+                start == 0 || last_patterns[start].is_some()
+            });
+            last_patterns.push(last_pattern);
+        });
+
+        last_patterns
+    }
+
+    /// How many stripes from the start of the design follow some pattern,
+    /// even if only partially
+    fn matching_stripes(&self, design: &[u8]) -> usize {
+        let mut node = 0;
+
+        for (idx, stripe) in design.iter().enumerate() {
+            match self.automaton.child(node, *stripe) {
+                Some(child) => node = child,
+                None => return idx,
+            }
+        }
+
+        design.len()
+    }
+
+    /// Dynamic programming over the prefixes of the design: the value for
+    /// each prefix adds up the values of the prefixes that leave one of the
+    /// patterns before its end, starting from the one for the empty prefix.
+    /// Only the values that a pattern can reach back to are kept around.
+    fn fold_prefixes<D, T, F>(&self, design: D, empty: T, none: T, add: F) -> T
+    where
+        D: IntoIterator<Item = u8>,
+        T: Copy,
+        F: Fn(T, T) -> T,
+    {
+        let window = self.longest_pattern + 1;
+        let mut values = vec![none; window];
+        values[0] = empty;
+        let mut length = 0;
+
+        self.scan(design, |end, matches| {
+            let value = matches.iter().fold(none, |value, &pattern| {
+                add(value, values[(end - self.patterns[pattern].len()) % window])
+            });
+            values[end % window] = value;
+            length = end;
+        });
+
+        values[length % window]
+    }
+
+    /// Calls back with the length read so far and the patterns ending there,
+    /// for every stripe in the design
+    fn scan<D, F>(&self, design: D, mut on_stripe: F)
+    where
+        D: IntoIterator<Item = u8>,
+        F: FnMut(usize, &[usize]),
+    {
+        let mut state = 0;
+
+        for (idx, stripe) in design.into_iter().enumerate() {
+            state = self.automaton.next_state(state, stripe);
+            on_stripe(idx + 1, self.automaton.outputs(state));
+        }
+    }
+}
+
+/// Where a design stops being possible: its longest prefix that can be made
+/// of patterns, and the first stripe that no pattern placed after any such
+/// prefix reaches.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MatchFailure {
+    pub longest_prefix: usize,
+    pub failing_position: usize,
+}
+
+/// The available towel patterns, with a matcher to arrange designs from them
+pub struct TowelSet {
+    patterns: Vec<String>,
+    matcher: TowelMatcher,
+}
+
+impl TowelSet {
+    pub fn new(patterns: Vec<String>) -> Self {
+        let matcher = TowelMatcher::new(patterns.iter().map(|pattern| pattern.bytes()));
+
+        Self { patterns, matcher }
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    pub fn is_possible(&self, design: &str) -> bool {
+        self.matcher.is_possible(design.bytes())
+    }
+
+    pub fn arrangements(&self, design: &str) -> Option<u64> {
+        self.matcher.arrangements(design.bytes())
+    }
+
+    pub fn arrangement(&self, design: &str) -> Option<Vec<&str>> {
+        self.matcher.arrangement(design.bytes()).map(|arrangement| {
+            arrangement
+                .into_iter()
+                .map(|pattern| std::str::from_utf8(pattern).unwrap())
+                .collect()
+        })
+    }
+
+    /// Patterns that can be made of other patterns, or that are repeated
+    pub fn redundant_patterns(&self) -> Vec<&str> {
+        let mut seen = HashSet::new();
+
+        self.patterns
+            .iter()
+            .filter(|pattern| {
+                !seen.insert(pattern.as_str()) || self.matcher.is_splittable(pattern.as_bytes())
+            })
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// The patterns that are not redundant. Every redundant pattern splits
+    /// into shorter ones, which in the end split into these, so they make
+    /// the same designs as the whole set. None of them can be left out as
+    /// no other patterns make them up, so there is no smaller such subset.
+    pub fn minimal_subset(&self) -> TowelSet {
+        let mut seen = HashSet::new();
+        let patterns = self
+            .patterns
+            .iter()
+            .filter(|pattern| {
+                seen.insert(pattern.as_str()) && !self.matcher.is_splittable(pattern.as_bytes())
+            })
+            .cloned()
+            .collect();
+
+        TowelSet::new(patterns)
+    }
+
+    /// Where the design fails to be arranged, or nothing if it is possible
+    pub fn diagnose(&self, design: &str) -> Option<MatchFailure> {
+        let design = design.as_bytes();
+        let is_possible = self.matcher.possible_prefixes(design);
+        if is_possible[design.len()] {
+            return None;
+        }
+
+        let possible_ends = (0..design.len()).filter(|&end| is_possible[end]);
+        let longest_prefix = possible_ends.clone().max().unwrap();
+        let failing_position = possible_ends
+            .map(|end| end + self.matcher.matching_stripes(&design[end..]))
+            .max()
+            .unwrap();
+
+        Some(MatchFailure {
+            longest_prefix,
+            failing_position,
+        })
+    }
 }
 
 #[cfg(test)]
@@ -44,17 +300,180 @@ mod tests {
     }
 
     #[test]
-    fn test_find_first_prefix() {
+    fn test_is_possible() {
+        let patterns = ["r", "wr", "b", "g", "bwu", "rb", "gb", "br"];
+        let matcher = TowelMatcher::new(patterns.iter().map(|pattern| pattern.bytes()));
         let cases = vec![
-            ("bcd", vec!["a", "b", "bc", "bcd"], Some(1)),
-            ("bcd", vec!["b", "bc", "bcd"], Some(0)),
-            ("bcd", vec!["ab", "abc", "abcd"], None),
-            ("bcd", vec!["a", "ab", "abc", "bcd"], Some(3)),
-            ("bwu", vec!["g", "bwu", "rb", "gb", "br"], Some(1)),
+            ("brwrr", true),
+            ("bggr", true),
+            ("ubwu", false),
+            ("bwurrg", true),
+            ("bbrgwb", false),
+            ("bwu", true),
+            ("", true),
         ];
 
-        for (design, patterns, expected) in cases {
-            assert_eq!(expected, find_first_prefix(design, &patterns))
+        for (design, expected) in cases {
+            assert_eq!(expected, matcher.is_possible(design.bytes()), "{}", design);
         }
     }
+
+    #[test]
+    fn test_arrangements() {
+        let input = fs::read_to_string("data/day19/test_input").unwrap();
+        let (patterns, designs) = parse_input(&input);
+        let matcher = TowelMatcher::new(patterns.iter().map(|pattern| pattern.bytes()));
+        let arrangements: Vec<Option<u64>> = designs
+            .iter()
+            .map(|design| matcher.arrangements(design.bytes()))
+            .collect();
+
+        assert_eq!(
+            vec![2, 1, 4, 6, 0, 1, 2, 0],
+            arrangements
+                .into_iter()
+                .map(Option::unwrap)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            6,
+            designs
+                .iter()
+                .filter(|design| matcher.is_possible(design.bytes()))
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_arrangement() {
+        let patterns = ["r", "wr", "b", "g", "bwu", "rb", "gb", "br"];
+        let matcher = TowelMatcher::new(patterns.iter().map(|pattern| pattern.bytes()));
+
+        for design in ["brwrr", "bggr", "gbbr", "rrbgbr", "bwurrg", "brgr"] {
+            let arrangement = matcher.arrangement(design.bytes()).unwrap();
+            assert_eq!(design.as_bytes(), arrangement.concat(), "{}", design);
+            assert!(arrangement
+                .iter()
+                .all(|pattern| patterns.contains(&std::str::from_utf8(pattern).unwrap())));
+        }
+        assert_eq!(None, matcher.arrangement("ubwu".bytes()));
+        assert_eq!(None, matcher.arrangement("bbrgwb".bytes()));
+        assert_eq!(Some(vec![]), matcher.arrangement("".bytes()));
+    }
+
+    #[test]
+    fn test_byte_streams() {
+        let patterns: &[u8] = b"ab\nb\naab";
+        let matcher = TowelMatcher::new(
+            patterns
+                .split(|&byte| byte == b'\n')
+                .map(|pattern| pattern.iter().copied()),
+        );
+
+        // Read one byte at a time, without the design ever being in memory
+        let repeated = |times: usize| b"ab".iter().copied().cycle().take(2 * times);
+        assert_eq!(Some(1), matcher.arrangements(repeated(100_000)));
+        let design = std::iter::once(b'a')
+            .chain(repeated(20))
+            .chain(std::iter::once(b'b'));
+        assert_eq!(
+            Some(vec![&b"aab"[..], &b"ab"[..], &b"b"[..]]),
+            matcher.arrangement(design.clone()).map(|arrangement| {
+                let mut kinds = arrangement;
+                kinds.dedup();
+                kinds
+            })
+        );
+        assert_eq!(Some(1), matcher.arrangements(design));
+
+        // Arrangements of n stripes from 1 and 2 stripe patterns are Fibonacci
+        let matcher = TowelMatcher::new([vec![b'a'], vec![b'a', b'a']]);
+        assert_eq!(
+            Some(4660046610375530309),
+            matcher.arrangements(std::iter::repeat_n(b'a', 90))
+        );
+    }
+
+    #[test]
+    fn test_arrangements_overflow() {
+        let matcher = TowelMatcher::new([vec![b'a'], vec![b'a', b'a']]);
+        let stripes = |count: usize| std::iter::repeat_n(b'a', count);
+
+        // Fibonacci numbers go past u64::MAX from the 94th on
+        assert_eq!(
+            Some(12200160415121876738),
+            matcher.arrangements(stripes(92))
+        );
+        assert_eq!(None, matcher.arrangements(stripes(93)));
+        assert_eq!(None, matcher.arrangements(stripes(1000)));
+
+        // Too many ways to make the a's, but none to make the b after them
+        assert_eq!(Some(0), matcher.arrangements(stripes(100).chain([b'b'])));
+    }
+
+    #[test]
+    fn test_redundant_patterns() {
+        let input = fs::read_to_string("data/day19/test_input").unwrap();
+        let (patterns, designs) = parse_input(&input);
+        let towels = TowelSet::new(patterns);
+
+        assert_eq!(vec!["rb", "gb", "br"], towels.redundant_patterns());
+
+        let minimal = towels.minimal_subset();
+        assert_eq!(vec!["r", "wr", "b", "g", "bwu"], minimal.patterns());
+        assert!(minimal.redundant_patterns().is_empty());
+        for design in designs.iter() {
+            assert_eq!(towels.is_possible(design), minimal.is_possible(design));
+        }
+
+        let repeated = TowelSet::new(vec!["ab".to_string(), "b".to_string(), "ab".to_string()]);
+        assert_eq!(vec!["ab"], repeated.redundant_patterns());
+        assert_eq!(vec!["ab", "b"], repeated.minimal_subset().patterns());
+
+        // Far more splits of the long pattern than fit in a u64
+        let long = "a".repeat(200);
+        let towels = TowelSet::new(vec!["a".to_string(), "aa".to_string(), long.clone()]);
+        assert_eq!(vec!["aa", long.as_str()], towels.redundant_patterns());
+        assert_eq!(vec!["a"], towels.minimal_subset().patterns());
+
+        // aab ends with both other patterns, but neither leaves a possible prefix
+        let towels = TowelSet::new(vec!["ab".to_string(), "b".to_string(), "aab".to_string()]);
+        assert!(towels.redundant_patterns().is_empty());
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let input = fs::read_to_string("data/day19/test_input").unwrap();
+        let (patterns, _) = parse_input(&input);
+        let towels = TowelSet::new(patterns);
+
+        assert_eq!(None, towels.diagnose("brwrr"));
+        assert_eq!(
+            Some(MatchFailure {
+                longest_prefix: 0,
+                failing_position: 0
+            }),
+            towels.diagnose("ubwu")
+        );
+        // bbrg can be made, then wr only matches the w
+        assert_eq!(
+            Some(MatchFailure {
+                longest_prefix: 4,
+                failing_position: 5
+            }),
+            towels.diagnose("bbrgwb")
+        );
+        // bwu goes past the last possible prefix, b, up to the x
+        assert_eq!(
+            Some(MatchFailure {
+                longest_prefix: 1,
+                failing_position: 2
+            }),
+            towels.diagnose("bwx")
+        );
+        assert_eq!(
+            Some(vec!["bwu", "r", "r", "g"]),
+            towels.arrangement("bwurrg")
+        );
+    }
 }
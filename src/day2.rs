@@ -1,78 +1,124 @@
 use std::cmp::Ordering;
+use std::ops::RangeInclusive;
 
 type Level = isize;
 pub struct Report {
     levels: Vec<Level>,
 }
 
-impl Report {
-    pub fn is_safe_with_dampener(&self) -> bool {
-        if Report::infer_ordering_slice(&self.levels[1..])
-            .map(|expected_ordering| Report::is_safe_slice(&self.levels[1..], expected_ordering))
-            .unwrap_or(false)
-        {
-            return true;
-        }
-
-        for skip_idx in 1..self.levels.len() - 1 {
-            let prefix = &self.levels[..skip_idx];
-            let suffix = &self.levels[skip_idx + 1..];
-            let is_safe = Report::infer_ordering_slice(prefix)
-                .or(Report::infer_ordering_slice(suffix))
-                .map(|expected_ordering| {
-                    let prefix_safe = Report::is_safe_slice(prefix, expected_ordering);
-                    let suffix_safe = Report::is_safe_slice(suffix, expected_ordering);
-
-                    prefix_safe
-                        && Report::is_safe_step(
-                            &prefix[skip_idx - 1],
-                            &suffix[0],
-                            expected_ordering,
-                        )
-                        && suffix_safe
-                })
-                .unwrap_or(false);
+/// What makes a report safe: levels all increasing or all decreasing by a
+/// step in the given range, after removing up to `tolerance` levels. A report
+/// needs at least two levels left to tell which way it goes.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SafetyRule {
+    pub steps: RangeInclusive<Level>,
+    pub tolerance: usize,
+}
 
-            if is_safe {
-                return true;
-            }
+impl Default for SafetyRule {
+    fn default() -> Self {
+        Self {
+            steps: 1..=3,
+            tolerance: 0,
         }
+    }
+}
 
-        Report::infer_ordering_slice(&self.levels[..self.levels.len() - 1])
-            .map(|expected_ordering| {
-                Report::is_safe_slice(&self.levels[..self.levels.len() - 1], expected_ordering)
-            })
-            .unwrap_or(false)
+impl SafetyRule {
+    /// The rule with the problem dampener, which tolerates a single bad level
+    pub fn with_dampener() -> Self {
+        Self {
+            tolerance: 1,
+            ..Default::default()
+        }
     }
 
-    fn is_safe_slice(slice: &[Level], expected_ordering: Ordering) -> bool {
-        slice
-            .windows(2)
-            .map(|window| Report::is_safe_step(&window[0], &window[1], expected_ordering))
-            .all(|x| x)
+    pub fn is_safe_step(&self, current: Level, next: Level, expected_ordering: Ordering) -> bool {
+        let step = match expected_ordering {
+            Ordering::Less => next - current,
+            Ordering::Greater => current - next,
+            Ordering::Equal => return false,
+        };
+
+        self.steps.contains(&step)
     }
+}
 
-    fn infer_ordering_slice(levels: &[Level]) -> Option<Ordering> {
-        if levels.len() < 2 {
-            return None;
-        }
+/// The levels to take out of a report to make it safe
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Removals {
+    pub count: usize,
+    pub indices: Vec<usize>,
+}
 
-        match levels[0].cmp(&levels[1]) {
-            Ordering::Equal => None,
-            ordering => Some(ordering),
-        }
+impl Report {
+    pub fn is_safe(&self) -> bool {
+        self.is_safe_with(&SafetyRule::default())
     }
 
-    pub fn is_safe_step(current: &Level, next: &Level, expected_ordering: Ordering) -> bool {
-        current.cmp(next) == expected_ordering && (current - next).abs() <= 3
+    pub fn is_safe_with_dampener(&self) -> bool {
+        self.is_safe_with(&SafetyRule::with_dampener())
     }
 
-    pub fn is_safe(&self) -> bool {
-        Report::infer_ordering_slice(&self.levels)
-            .map(|expected_ordering| Report::is_safe_slice(&self.levels, expected_ordering))
+    pub fn is_safe_with(&self, rule: &SafetyRule) -> bool {
+        self.minimum_removals(rule)
+            .map(|removals| removals.count <= rule.tolerance)
             .unwrap_or(false)
     }
 
+    /// The fewest levels to remove for the report to be safe under the rule,
+    /// regardless of its tolerance. The levels that stay are the longest
+    /// subsequence with safe steps, either increasing or decreasing, found
+    /// with a quadratic dynamic programming pass. There is no answer if not
+    /// even two levels can be kept.
+    pub fn minimum_removals(&self, rule: &SafetyRule) -> Option<Removals> {
+        let kept = [Ordering::Less, Ordering::Greater]
+            .into_iter()
+            .map(|ordering| self.longest_safe_subsequence(rule, ordering))
+            .max_by_key(Vec::len)
+            .filter(|kept| kept.len() >= 2)?;
+
+        let indices: Vec<usize> = (0..self.levels.len())
+            .filter(|idx| !kept.contains(idx))
+            .collect();
+
+        Some(Removals {
+            count: indices.len(),
+            indices,
+        })
+    }
+
+    /// Indices of the longest subsequence whose steps are all safe
+    fn longest_safe_subsequence(&self, rule: &SafetyRule, ordering: Ordering) -> Vec<usize> {
+        // Length of the longest safe subsequence ending at each level, and the
+        // level before it in that subsequence
+        let mut lengths = vec![1; self.levels.len()];
+        let mut previous: Vec<Option<usize>> = vec![None; self.levels.len()];
+
+        for next in 0..self.levels.len() {
+            for current in 0..next {
+                if lengths[current] + 1 > lengths[next]
+                    && rule.is_safe_step(self.levels[current], self.levels[next], ordering)
+                {
+                    lengths[next] = lengths[current] + 1;
+                    previous[next] = Some(current);
+                }
+            }
+        }
+
+        let Some(last) = (0..self.levels.len()).max_by_key(|&idx| lengths[idx]) else {
+            return vec![];
+        };
+
+        let mut subsequence = vec![last];
+        while let Some(idx) = previous[*subsequence.last().unwrap()] {
+            subsequence.push(idx);
+        }
+        subsequence.reverse();
+
+        subsequence
+    }
+
     fn from_str(line: &str) -> Report {
         let levels = line
             .split_whitespace()
@@ -85,3 +131,114 @@ impl Report {
 pub fn parse_input(input: &str) -> Vec<Report> {
     input.lines().map(Report::from_str).collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Rng;
+    use std::fs;
+
+    #[test]
+    fn test_safe_reports() {
+        let input = fs::read_to_string("data/day2/test_input").unwrap();
+        let reports = parse_input(&input);
+
+        assert_eq!(2, reports.iter().filter(|report| report.is_safe()).count());
+        assert_eq!(
+            4,
+            reports
+                .iter()
+                .filter(|report| report.is_safe_with_dampener())
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_minimum_removals() {
+        let input = fs::read_to_string("data/day2/test_input").unwrap();
+        let reports = parse_input(&input);
+        let rule = SafetyRule::default();
+        let removals: Vec<_> = reports
+            .iter()
+            .map(|report| report.minimum_removals(&rule).unwrap().indices)
+            .collect();
+
+        assert_eq!(
+            vec![vec![], vec![0, 1], vec![3, 4], vec![2], vec![3], vec![]],
+            removals
+        );
+
+        let wide = SafetyRule {
+            steps: 1..=5,
+            tolerance: 2,
+        };
+        assert!(reports[1].is_safe_with(&wide));
+        assert!(reports[2].is_safe_with(&wide));
+        assert!(reports.iter().all(|report| report.is_safe_with(&wide)));
+
+        let single = Report { levels: vec![4] };
+        assert_eq!(None, single.minimum_removals(&rule));
+    }
+
+    #[test]
+    fn test_minimum_removals_brute_force() {
+        let mut rng = Rng::new(11);
+        let mut next = move |bound: u64| rng.below(bound);
+        let rule = SafetyRule {
+            steps: 1..=2,
+            tolerance: 0,
+        };
+
+        // Equal levels never make a safe step, whatever is removed
+        let flat = Report {
+            levels: vec![3, 3, 3, 3],
+        };
+        assert_eq!(None, flat.minimum_removals(&rule));
+
+        // A peak keeps one side of it and loses the other
+        let peak = Report {
+            levels: vec![1, 2, 3, 2, 1],
+        };
+        assert_eq!(
+            Some(2),
+            peak.minimum_removals(&rule).map(|removals| removals.count)
+        );
+
+        for _ in 0..500 {
+            let levels: Vec<Level> = (0..2 + next(8)).map(|_| next(8) as Level).collect();
+            let is_safe = |kept: &[Level]| {
+                kept.len() >= 2
+                    && (kept
+                        .windows(2)
+                        .all(|pair| rule.is_safe_step(pair[0], pair[1], Ordering::Less))
+                        || kept
+                            .windows(2)
+                            .all(|pair| rule.is_safe_step(pair[0], pair[1], Ordering::Greater)))
+            };
+            let fewest = (0..1u32 << levels.len())
+                .filter(|kept| {
+                    let kept: Vec<Level> = (0..levels.len())
+                        .filter(|idx| kept & (1 << idx) != 0)
+                        .map(|idx| levels[idx])
+                        .collect();
+                    is_safe(&kept)
+                })
+                .map(|kept| levels.len() - kept.count_ones() as usize)
+                .min();
+
+            let report = Report {
+                levels: levels.clone(),
+            };
+            let removals = report.minimum_removals(&rule);
+            assert_eq!(fewest, removals.as_ref().map(|removals| removals.count));
+
+            if let Some(removals) = removals {
+                let kept: Vec<Level> = (0..levels.len())
+                    .filter(|idx| !removals.indices.contains(idx))
+                    .map(|idx| levels[idx])
+                    .collect();
+                assert!(is_safe(&kept));
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::common::Automaton;
 use crate::parse::TextGrid;
 use crate::spatial::Point2D;
 use crate::spatial::Point2DCast;
@@ -32,8 +33,7 @@ impl WordPattern {
         self.word
             .chars()
             .zip(ray)
-            .map(|(word_char, puzzle_char)| puzzle_char.map(|p| p == word_char).unwrap_or(false))
-            .all(|matches| matches)
+            .all(|(word_char, puzzle_char)| puzzle_char.map(|p| p == word_char).unwrap_or(false))
     }
 }
 
@@ -44,7 +44,7 @@ impl PatternSpotter for WordPattern {
         let first = self.word.as_bytes()[0] as char;
         text_grid
             .iter()
-            .map(|(position, letter)| {
+            .flat_map(|(position, letter)| {
                 if letter != first {
                     return vec![];
                 }
@@ -55,13 +55,13 @@ impl PatternSpotter for WordPattern {
                         self.match_word(text_grid, &position.cast().unwrap(), direction)
                     })
                     .map(|direction| (position.cast().unwrap(), *direction))
-                    .collect()
+                    .collect::<Vec<_>>()
             })
-            .flatten()
             .collect()
     }
 }
 
+#[derive(Default)]
 pub struct CrossMASPattern {}
 
 impl PatternSpotter for CrossMASPattern {
@@ -91,7 +91,7 @@ impl CrossMASPattern {
     }
 
     fn match_cross_mas(&self, text_grid: &TextGrid, start: Position) -> bool {
-        let corners: String = vec![Direction::NE, Direction::SE, Direction::SW, Direction::NW]
+        let corners: String = [Direction::NE, Direction::SE, Direction::SW, Direction::NW]
             .iter()
             .filter_map(|direction| text_grid.char_at(start + direction.as_delta()))
             .collect();
@@ -100,20 +100,198 @@ impl CrossMASPattern {
             return false;
         }
 
-        match corners.as_str() {
-            "MMSS" => true,
-            "MSSM" => true,
-            "SSMM" => true,
-            "SMMS" => true,
-            _ => false,
+        matches!(corners.as_str(), "MMSS" | "MSSM" | "SSMM" | "SMMS")
+    }
+}
+
+/// A small grid of letters to look for, where '.' matches any letter. It is
+/// written one row at a time separated by slashes, e.g. "M.S/.A./M.S" for
+/// the X-MAS, and may show up rotated or reflected.
+pub struct GridPattern {
+    rows: Vec<Vec<char>>,
+}
+
+pub const WILDCARD: char = '.';
+
+/// One of the 8 ways of rotating and reflecting a pattern: first reflected
+/// left to right if `reflected`, then rotated clockwise by `quarter_turns`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Transform {
+    pub quarter_turns: u8,
+    pub reflected: bool,
+}
+
+impl Transform {
+    pub fn all() -> [Self; 8] {
+        let mut transforms = [Self {
+            quarter_turns: 0,
+            reflected: false,
+        }; 8];
+        for (idx, transform) in transforms.iter_mut().enumerate() {
+            transform.quarter_turns = (idx % 4) as u8;
+            transform.reflected = idx >= 4;
+        }
+
+        transforms
+    }
+
+    fn apply(&self, rows: &[Vec<char>]) -> Vec<Vec<char>> {
+        let mut rows: Vec<Vec<char>> = rows.to_vec();
+
+        if self.reflected {
+            rows.iter_mut().for_each(|row| row.reverse());
+        }
+        for _ in 0..self.quarter_turns {
+            // The first column, read bottom up, becomes the first row
+            let height = rows.len();
+            let width = rows.first().map(Vec::len).unwrap_or(0);
+            rows = (0..width)
+                .map(|column| (0..height).rev().map(|row| rows[row][column]).collect())
+                .collect();
+        }
+
+        rows
+    }
+}
+
+impl GridPattern {
+    /// The distinct shapes the pattern takes, each with the first transform
+    /// that produces it. Symmetric patterns have fewer than 8 of them, so
+    /// the same occurrence is not reported more than once.
+    fn variants(&self) -> Vec<(Transform, Vec<Vec<char>>)> {
+        let mut variants: Vec<(Transform, Vec<Vec<char>>)> = vec![];
+
+        for transform in Transform::all() {
+            let rows = transform.apply(&self.rows);
+            if variants.iter().all(|(_, other)| *other != rows) {
+                variants.push((transform, rows));
+            }
+        }
+
+        variants
+    }
+
+    fn matches_at(rows: &[Vec<char>], text_grid: &TextGrid, top_left: Position) -> bool {
+        rows.iter().enumerate().all(|(y, row)| {
+            row.iter().enumerate().all(|(x, &letter)| {
+                let position = top_left + Position::new(x as Coordinate, y as Coordinate);
+                letter == WILDCARD || text_grid.char_at(position) == Some(letter)
+            })
+        })
+    }
+}
+
+impl PatternSpotter for GridPattern {
+    /// Top left corner of the transformed pattern, and the transform
+    type Occurrence = (Position, Transform);
+
+    fn find_pattern_occurrences(&self, text_grid: &TextGrid) -> Vec<Self::Occurrence> {
+        let variants = self.variants();
+
+        text_grid
+            .iter()
+            .flat_map(|(position, _)| {
+                let position: Position = position.cast().unwrap();
+                variants
+                    .iter()
+                    .filter(move |(_, rows)| GridPattern::matches_at(rows, text_grid, position))
+                    .map(move |(transform, _)| (position, *transform))
+            })
+            .collect()
+    }
+}
+
+impl FromStr for GridPattern {
+    type Err = ();
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let rows: Vec<Vec<char>> = pattern
+            .split('/')
+            .map(|row| row.chars().collect())
+            .collect();
+
+        if rows[0].is_empty() || rows.iter().any(|row| row.len() != rows[0].len()) {
+            return Err(());
         }
+
+        Ok(Self { rows })
+    }
+}
+
+/// Looks for several words at once, in the 8 directions. Each line of the
+/// grid, in each direction, is scanned a single time with an Aho-Corasick
+/// automaton over all the words.
+pub struct WordSearch {
+    words: Vec<String>,
+    automaton: Automaton<char>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct WordOccurrence {
+    /// Index of the word in the list given to WordSearch::new
+    pub word: usize,
+    pub start: Position,
+    pub direction: Direction,
+}
+
+impl WordSearch {
+    pub fn new<S: AsRef<str>>(words: &[S]) -> Self {
+        let words: Vec<String> = words.iter().map(|word| word.as_ref().to_string()).collect();
+        let automaton = Automaton::new(words.iter().map(|word| word.chars()));
+
+        Self { words, automaton }
+    }
+
+    /// Every position of the grid that is the first one of a line going in
+    /// the given direction, i.e. the one before it is out of the grid.
+    fn line_starts(text_grid: &TextGrid, direction: Direction) -> Vec<Position> {
+        text_grid
+            .iter()
+            .map(|(position, _)| position.cast().unwrap())
+            .filter(|&position: &Position| {
+                text_grid.char_at(position - direction.as_delta()).is_none()
+            })
+            .collect()
+    }
+}
+
+impl PatternSpotter for WordSearch {
+    type Occurrence = WordOccurrence;
+
+    fn find_pattern_occurrences(&self, text_grid: &TextGrid) -> Vec<Self::Occurrence> {
+        let mut occurrences = vec![];
+
+        for direction in Direction::all() {
+            let delta = direction.as_delta();
+
+            for start in WordSearch::line_starts(text_grid, direction) {
+                let mut state = 0;
+                let line = iter::successors(Some(start), |&position| Some(position + delta))
+                    .map_while(|position| text_grid.char_at(position).map(|c| (position, c)));
+
+                for (position, letter) in line {
+                    state = self.automaton.next_state(state, letter);
+
+                    for &word in self.automaton.outputs(state) {
+                        let length = self.words[word].chars().count() as Coordinate;
+                        occurrences.push(WordOccurrence {
+                            word,
+                            start: position - delta * (length - 1),
+                            direction,
+                        });
+                    }
+                }
+            }
+        }
+
+        occurrences
     }
 }
 
 type Coordinate = i16;
 type Position = Point2D<Coordinate>;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum Direction {
     N,
     NE,
@@ -126,7 +304,7 @@ pub enum Direction {
 }
 
 impl Direction {
-    fn all() -> [Self; 8] {
+    pub fn all() -> [Self; 8] {
         [
             Self::N,
             Self::NE,
@@ -171,3 +349,135 @@ impl FromStr for Puzzle {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::fs;
+
+    fn puzzle() -> Puzzle {
+        fs::read_to_string("data/day4/test_input")
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_word_pattern() {
+        let puzzle = puzzle();
+
+        assert_eq!(
+            18,
+            puzzle
+                .find_pattern_occurrences(WordPattern::new("XMAS".to_string()))
+                .len()
+        );
+        assert_eq!(
+            9,
+            puzzle
+                .find_pattern_occurrences(CrossMASPattern::new())
+                .len()
+        );
+    }
+
+    #[test]
+    fn test_grid_pattern() {
+        let puzzle = puzzle();
+        let cross: GridPattern = "M.S/.A./M.S".parse().unwrap();
+        let occurrences = puzzle.find_pattern_occurrences(cross);
+
+        assert_eq!(9, occurrences.len());
+        // The pattern is symmetric, so only 4 of the transforms show up
+        let transforms: HashSet<_> = occurrences
+            .iter()
+            .map(|(_, transform)| *transform)
+            .collect();
+        assert_eq!(4, transforms.len());
+        // Occurrences are anchored at the top left corner, an A away
+        let centres: HashSet<Position> = occurrences
+            .iter()
+            .map(|(position, _)| *position + Position::new(1, 1))
+            .collect();
+        let expected: HashSet<Position> = puzzle
+            .find_pattern_occurrences(CrossMASPattern::new())
+            .into_iter()
+            .collect();
+        assert_eq!(expected, centres);
+
+        // A single row pattern only goes horizontally and vertically
+        let straight: GridPattern = "XMAS".parse().unwrap();
+        let expected = puzzle
+            .find_pattern_occurrences(WordPattern::new("XMAS".to_string()))
+            .into_iter()
+            .filter(|(_, direction)| {
+                matches!(
+                    direction,
+                    Direction::N | Direction::E | Direction::S | Direction::W
+                )
+            })
+            .count();
+        assert_eq!(expected, puzzle.find_pattern_occurrences(straight).len());
+
+        assert!("MS/A".parse::<GridPattern>().is_err());
+    }
+
+    #[test]
+    fn test_transforms() {
+        let rows = vec![vec!['a', 'b', 'c'], vec!['d', 'e', 'f']];
+        let rotated = Transform {
+            quarter_turns: 1,
+            reflected: false,
+        };
+        let reflected = Transform {
+            quarter_turns: 0,
+            reflected: true,
+        };
+
+        assert_eq!(
+            vec![vec!['d', 'a'], vec!['e', 'b'], vec!['f', 'c']],
+            rotated.apply(&rows)
+        );
+        assert_eq!(
+            vec![vec!['c', 'b', 'a'], vec!['f', 'e', 'd']],
+            reflected.apply(&rows)
+        );
+        let shapes: HashSet<_> = Transform::all()
+            .iter()
+            .map(|transform| transform.apply(&rows))
+            .collect();
+        assert_eq!(8, shapes.len());
+    }
+
+    #[test]
+    fn test_word_search() {
+        let puzzle = puzzle();
+        let xmas: HashSet<(Position, Direction)> = puzzle
+            .find_pattern_occurrences(WordPattern::new("XMAS".to_string()))
+            .into_iter()
+            .collect();
+        let occurrences = puzzle.find_pattern_occurrences(WordSearch::new(&["XMAS", "MAS", "AS"]));
+        let found = |word: usize| -> HashSet<(Position, Direction)> {
+            occurrences
+                .iter()
+                .filter(|occurrence| occurrence.word == word)
+                .map(|occurrence| (occurrence.start, occurrence.direction))
+                .collect()
+        };
+
+        assert_eq!(xmas, found(0));
+        // Every XMAS has a MAS and an AS inside it
+        for (start, direction) in xmas.iter() {
+            let delta = direction.as_delta();
+            assert!(found(1).contains(&(*start + delta, *direction)));
+            assert!(found(2).contains(&(*start + delta * 2, *direction)));
+        }
+        for word in [1, 2] {
+            let word_pattern = WordPattern::new(["XMAS", "MAS", "AS"][word].to_string());
+            assert_eq!(
+                puzzle.find_pattern_occurrences(word_pattern).len(),
+                found(word).len()
+            );
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 
 pub fn parse_input(input: &str) -> (HashSet<OrderingRule>, Vec<PageUpdate>) {
     let (rules_block, updates_block) = input.split_once("\n\n").unwrap();
@@ -11,7 +12,7 @@ pub fn parse_input(input: &str) -> (HashSet<OrderingRule>, Vec<PageUpdate>) {
 
 type Page = u8;
 
-#[derive(Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct OrderingRule {
     before: Page,
     after: Page,
@@ -28,36 +29,301 @@ impl OrderingRule {
     }
 }
 
+/// The ordering rules as a graph, with an edge from each page to every page
+/// that must go after it.
+pub struct RuleGraph {
+    successors: HashMap<Page, Vec<Page>>,
+}
+
+/// Pages in an order that satisfies every rule between them. The order is
+/// unique when each pair of consecutive pages is bound by a rule; otherwise
+/// some of the pages could be swapped.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TopologicalOrder {
+    pub pages: Vec<Page>,
+    pub is_unique: bool,
+}
+
+/// Pages whose rules contradict each other, in rule order: each page must go
+/// before the next one, and the last one before the first.
+#[derive(Debug, Eq, PartialEq)]
+pub struct RuleCycle {
+    pub pages: Vec<Page>,
+}
+
+impl fmt::Display for RuleCycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for page in self.pages.iter() {
+            write!(f, "{} -> ", page)?;
+        }
+        write!(f, "{}", self.pages[0])
+    }
+}
+
+impl RuleGraph {
+    /// Sorts the given pages with Kahn's algorithm, only taking into account
+    /// the rules between them. Among pages that are free to go next, the one
+    /// listed first wins, so orders that are not unique are still stable.
+    pub fn topological_sort(&self, pages: &[Page]) -> Result<TopologicalOrder, RuleCycle> {
+        let included: HashSet<Page> = pages.iter().copied().collect();
+        let mut in_degrees: HashMap<Page, usize> = pages.iter().map(|&page| (page, 0)).collect();
+        for page in pages.iter() {
+            for successor in self.successors_within(*page, &included) {
+                *in_degrees.get_mut(&successor).unwrap() += 1;
+            }
+        }
+
+        let mut ready: VecDeque<Page> = pages
+            .iter()
+            .copied()
+            .filter(|page| in_degrees[page] == 0)
+            .collect();
+        let mut sorted = vec![];
+        let mut is_unique = true;
+
+        while let Some(page) = ready.pop_front() {
+            is_unique &= ready.is_empty();
+            sorted.push(page);
+
+            for successor in self.successors_within(page, &included) {
+                let in_degree = in_degrees.get_mut(&successor).unwrap();
+                *in_degree -= 1;
+                if *in_degree == 0 {
+                    ready.push_back(successor);
+                }
+            }
+        }
+
+        if sorted.len() < pages.len() {
+            let remaining: HashSet<Page> = in_degrees
+                .into_iter()
+                .filter(|&(_, in_degree)| in_degree > 0)
+                .map(|(page, _)| page)
+                .collect();
+            return Err(self.find_cycle(&remaining));
+        }
+
+        Ok(TopologicalOrder {
+            pages: sorted,
+            is_unique,
+        })
+    }
+
+    /// Every page left over by Kahn's algorithm has a predecessor that is
+    /// also left over, so walking backwards eventually repeats a page.
+    fn find_cycle(&self, remaining: &HashSet<Page>) -> RuleCycle {
+        let predecessor = |page: Page| {
+            *remaining
+                .iter()
+                .filter(|&&candidate| {
+                    self.successors_within(candidate, remaining)
+                        .any(|p| p == page)
+                })
+                .min()
+                .unwrap()
+        };
+
+        let mut walk = vec![*remaining.iter().min().unwrap()];
+        loop {
+            let next = predecessor(*walk.last().unwrap());
+            if let Some(idx) = walk.iter().position(|&page| page == next) {
+                let mut pages = walk.split_off(idx);
+                // The walk went against the rules, so flip it around
+                pages[1..].reverse();
+                return RuleCycle { pages };
+            }
+            walk.push(next);
+        }
+    }
+
+    /// Whether there is a chain of rules from one page to another, going only
+    /// through the given pages.
+    fn reaches(&self, from: Page, to: Page, included: &HashSet<Page>) -> bool {
+        let mut pending = vec![from];
+        let mut visited = HashSet::from([from]);
+
+        while let Some(page) = pending.pop() {
+            for successor in self.successors_within(page, included) {
+                if successor == to {
+                    return true;
+                }
+                if visited.insert(successor) {
+                    pending.push(successor);
+                }
+            }
+        }
+
+        false
+    }
+
+    fn rule(&self, before: Page, after: Page) -> Option<OrderingRule> {
+        self.successors
+            .get(&before)
+            .filter(|successors| successors.contains(&after))
+            .map(|_| OrderingRule { before, after })
+    }
+
+    fn successors_within<'a>(
+        &'a self,
+        page: Page,
+        included: &'a HashSet<Page>,
+    ) -> impl Iterator<Item = Page> + 'a {
+        self.successors
+            .get(&page)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|successor| included.contains(successor))
+    }
+}
+
+impl From<&HashSet<OrderingRule>> for RuleGraph {
+    fn from(rules: &HashSet<OrderingRule>) -> Self {
+        let mut successors: HashMap<Page, Vec<Page>> = HashMap::new();
+
+        for rule in rules.iter() {
+            successors.entry(rule.before).or_default().push(rule.after);
+        }
+
+        Self { successors }
+    }
+}
+
+/// A page printed after another one it should have gone before
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Violation {
+    pub page: Page,
+    pub previous_page: Page,
+    pub rule: OrderingRule,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ValidationReport {
+    /// Every pair of pages in the wrong order, in update order
+    pub violations: Vec<Violation>,
+    /// The fewest pages that have to be taken out and put back elsewhere to
+    /// make the update valid, or the cycle that makes it impossible.
+    pub pages_to_move: Result<Vec<Page>, RuleCycle>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
 pub struct PageUpdate {
     pages: Vec<Page>,
 }
 
 impl PageUpdate {
-    pub fn sorted(&self, rules: &HashSet<OrderingRule>) -> Self {
-        let mut sorted_pages = vec![];
+    pub fn sorted(&self, rules: &RuleGraph) -> Result<Self, RuleCycle> {
+        let order = rules.topological_sort(&self.pages)?;
 
-        for page in self.pages.iter() {
-            PageUpdate::add_page_sorted(rules, &mut sorted_pages, *page);
+        Ok(Self { pages: order.pages })
+    }
+
+    pub fn validate(&self, rules: &RuleGraph) -> ValidationReport {
+        let violations = self
+            .pages
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, &page)| {
+                self.pages[..idx].iter().filter_map(move |&previous_page| {
+                    rules.rule(page, previous_page).map(|rule| Violation {
+                        page,
+                        previous_page,
+                        rule,
+                    })
+                })
+            })
+            .collect();
+
+        ValidationReport {
+            violations,
+            pages_to_move: self.pages_to_move(rules),
         }
+    }
+
+    /// The pages that stay put must already be in an order the rest of the
+    /// pages can be fitted around, so no rule may be broken between them,
+    /// even through chains of rules. The pairs breaking such chained rules
+    /// make up a partial order, and the largest set of pages that can stay is
+    /// one of its maximum antichains. By Dilworth's and König's theorems it
+    /// comes from a maximum matching between earlier and later pages.
+    fn pages_to_move(&self, rules: &RuleGraph) -> Result<Vec<Page>, RuleCycle> {
+        rules.topological_sort(&self.pages)?;
+
+        let included: HashSet<Page> = self.pages.iter().copied().collect();
+        let n = self.pages.len();
+        let inverted: Vec<Vec<usize>> = (0..n)
+            .map(|i| {
+                (i + 1..n)
+                    .filter(|&j| rules.reaches(self.pages[j], self.pages[i], &included))
+                    .collect()
+            })
+            .collect();
 
-        Self {
-            pages: sorted_pages,
+        let mut matched_left: Vec<Option<usize>> = vec![None; n];
+        let mut matched_right: Vec<Option<usize>> = vec![None; n];
+        for left in 0..n {
+            let mut visited = vec![false; n];
+            PageUpdate::augment(left, &inverted, &mut matched_right, &mut visited);
         }
+        for (right, left) in matched_right.iter().enumerate() {
+            if let Some(left) = *left {
+                matched_left[left] = Some(right);
+            }
+        }
+
+        // Alternating paths from the unmatched earlier pages
+        let mut reached_left = vec![false; n];
+        let mut reached_right = vec![false; n];
+        let mut pending: Vec<usize> = (0..n).filter(|&i| matched_left[i].is_none()).collect();
+        while let Some(left) = pending.pop() {
+            if reached_left[left] {
+                continue;
+            }
+            reached_left[left] = true;
+
+            for &right in inverted[left].iter() {
+                if !reached_right[right] {
+                    reached_right[right] = true;
+                    pending.extend(matched_right[right]);
+                }
+            }
+        }
+
+        Ok((0..n)
+            .filter(|&i| !reached_left[i] || reached_right[i])
+            .map(|i| self.pages[i])
+            .collect())
     }
 
-    fn add_page_sorted(
-        rules: &HashSet<OrderingRule>,
-        sorted_pages: &mut Vec<Page>,
-        target_page: Page,
-    ) {
-        for (idx, sorted_page) in sorted_pages.iter().enumerate() {
-            if PageUpdate::goes_before(rules, target_page, *sorted_page) {
-                sorted_pages.insert(idx, target_page);
-                return;
+    /// Kuhn's augmenting path step for bipartite matching
+    fn augment(
+        left: usize,
+        edges: &[Vec<usize>],
+        matched_right: &mut [Option<usize>],
+        visited: &mut [bool],
+    ) -> bool {
+        for &right in edges[left].iter() {
+            if visited[right] {
+                continue;
+            }
+            visited[right] = true;
+
+            let free = match matched_right[right] {
+                None => true,
+                Some(other) => PageUpdate::augment(other, edges, matched_right, visited),
+            };
+            if free {
+                matched_right[right] = Some(left);
+                return true;
             }
         }
 
-        sorted_pages.push(target_page);
+        false
     }
 
     pub fn middle_page(&self) -> u64 {
@@ -86,3 +352,160 @@ impl PageUpdate {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Rng;
+    use std::fs;
+
+    fn rules(pairs: &[(Page, Page)]) -> RuleGraph {
+        let rules: HashSet<OrderingRule> = pairs
+            .iter()
+            .map(|&(before, after)| OrderingRule { before, after })
+            .collect();
+        RuleGraph::from(&rules)
+    }
+
+    #[test]
+    fn test_sorted() {
+        let input = fs::read_to_string("data/day5/test_input").unwrap();
+        let (rules, updates) = parse_input(&input);
+        let graph = RuleGraph::from(&rules);
+
+        let valid: u64 = updates
+            .iter()
+            .filter(|update| update.is_valid(&rules))
+            .map(PageUpdate::middle_page)
+            .sum();
+        let fixed: u64 = updates
+            .iter()
+            .filter(|update| !update.is_valid(&rules))
+            .map(|update| update.sorted(&graph).unwrap().middle_page())
+            .sum();
+
+        assert_eq!(143, valid);
+        assert_eq!(123, fixed);
+        for update in updates.iter() {
+            let order = graph.topological_sort(&update.pages).unwrap();
+            assert!(order.is_unique);
+            assert!(update.sorted(&graph).unwrap().is_valid(&rules));
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let input = fs::read_to_string("data/day5/test_input").unwrap();
+        let (rules, updates) = parse_input(&input);
+        let graph = RuleGraph::from(&rules);
+
+        for update in updates.iter() {
+            let report = update.validate(&graph);
+            assert_eq!(update.is_valid(&rules), report.is_valid());
+            assert_eq!(report.is_valid(), report.pages_to_move == Ok(vec![]));
+        }
+
+        // 61,13,29 should be 61,29,13
+        let report = updates[4].validate(&graph);
+        assert_eq!(
+            vec![Violation {
+                page: 29,
+                previous_page: 13,
+                rule: OrderingRule {
+                    before: 29,
+                    after: 13
+                }
+            }],
+            report.violations
+        );
+        assert_eq!(Ok(1), report.pages_to_move.map(|pages| pages.len()));
+
+        // 97,13,75,29,47 is fixed by moving 13 and 29 after 47
+        let report = updates[5].validate(&graph);
+        assert_eq!(4, report.violations.len());
+        assert_eq!(Ok(vec![13, 29]), report.pages_to_move);
+    }
+
+    #[test]
+    fn test_pages_to_move_through_chains() {
+        // 3 and 1 break no rule between them, but 2 cannot be put back
+        // anywhere unless one of them moves too
+        let graph = rules(&[(1, 2), (2, 3)]);
+        let update = PageUpdate {
+            pages: vec![3, 2, 1],
+        };
+        let report = update.validate(&graph);
+
+        assert_eq!(2, report.violations.len());
+        assert_eq!(Ok(2), report.pages_to_move.map(|pages| pages.len()));
+
+        // A fully reversed chain keeps only one page where it is
+        let graph = rules(&[(1, 2), (2, 3), (3, 4), (4, 5)]);
+        let update = PageUpdate {
+            pages: vec![5, 4, 3, 2, 1],
+        };
+        assert_eq!(
+            Ok(4),
+            update
+                .validate(&graph)
+                .pages_to_move
+                .map(|pages| pages.len())
+        );
+
+        // Brute force over which pages stay, on random rules and updates
+        let mut rng = Rng::new(7);
+        let mut next = move |bound: u64| rng.below(bound);
+        for _ in 0..200 {
+            let pairs: Vec<(Page, Page)> = (0..next(15))
+                .map(|_| (next(8) as Page, next(8) as Page))
+                .filter(|(before, after)| before < after)
+                .collect();
+            let graph = rules(&pairs);
+            let mut pages: Vec<Page> = (0..8).collect();
+            for idx in (1..pages.len()).rev() {
+                pages.swap(idx, next(idx as u64 + 1) as usize);
+            }
+
+            let fewest = (0..1u32 << pages.len())
+                .filter(|&kept| {
+                    let kept: Vec<Page> = (0..pages.len())
+                        .filter(|idx| kept & (1 << idx) != 0)
+                        .map(|idx| pages[idx])
+                        .collect();
+                    // Kept pages are fine if some valid order of all the
+                    // pages keeps them as they are, so there must be one
+                    // with their current order added as extra rules
+                    let mut extended = pairs.clone();
+                    extended.extend(kept.windows(2).map(|pair| (pair[0], pair[1])));
+                    rules(&extended).topological_sort(&pages).is_ok()
+                })
+                .map(|kept| pages.len() - kept.count_ones() as usize)
+                .min()
+                .unwrap();
+            let update = PageUpdate { pages };
+            let moved = update.validate(&graph).pages_to_move.unwrap();
+
+            assert_eq!(fewest, moved.len());
+        }
+    }
+
+    #[test]
+    fn test_order_not_unique() {
+        let graph = rules(&[(1, 3), (2, 3)]);
+        let order = graph.topological_sort(&[3, 2, 1]).unwrap();
+
+        assert_eq!(vec![2, 1, 3], order.pages);
+        assert!(!order.is_unique);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let graph = rules(&[(1, 2), (2, 3), (3, 4), (4, 2), (5, 1)]);
+        let cycle = graph.topological_sort(&[5, 4, 3, 2, 1]).unwrap_err();
+
+        assert_eq!(vec![2, 3, 4], cycle.pages);
+        assert_eq!("2 -> 3 -> 4 -> 2", cycle.to_string());
+        // Rules between pages outside the update do not matter
+        assert!(graph.topological_sort(&[5, 1, 2, 3]).is_ok());
+    }
+}
@@ -1,81 +1,156 @@
-use crate::common::CycleDetection;
+use crate::common::{Cycle, CycleDetection, HashedCycleDetection};
+use crate::obstacles::{ExtendedObstacleIndex, NextObstacle, ObstacleIndex};
 use crate::spatial::{Direction, Orientation, Point2D};
-use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
-pub fn parse_input(input: &str) -> (Grid, Guard) {
+pub fn parse_input(input: &str) -> (Grid, Vec<Guard>) {
     let rows = input.lines().count();
     let columns = input.find('\n').unwrap();
-    let mut obstacles = HashSet::new();
-    let mut start = Point2D::new(0, 0);
+    let mut obstacles = HashMap::new();
+    let mut guards = vec![];
 
     // Iterate the rows in reverse order to have the origin at the bottom left
     // so directions works as expected, i.e. up is positive y, not negative.
     for (row, line) in input.lines().rev().enumerate() {
         for (column, tile) in line.chars().enumerate() {
             let position = Point2D::new(column as Coordinate, row as Coordinate);
-            match tile {
-                '#' => {
-                    obstacles.insert(position);
+            let facing = match tile {
+                '.' => continue,
+                '^' => Direction::Up,
+                '>' => Direction::Right,
+                'v' => Direction::Down,
+                '<' => Direction::Left,
+                // Any other tile is an obstacle, and its kind may matter for
+                // the turning rule
+                obstacle => {
+                    obstacles.insert(position, obstacle);
+                    continue;
                 }
-                '^' => {
-                    start = position;
-                }
-                _ => {}
-            }
+            };
+            guards.push(Guard { position, facing });
         }
     }
 
-    let grid = Grid::new(rows, columns, obstacles);
-    let guard = Guard {
-        position: start,
-        facing: Direction::Up,
-    };
+    // Rows were read bottom up, but guards are more naturally listed top down
+    guards.sort_by_key(|guard| (-guard.position.y, guard.position.x));
 
-    (grid, guard)
+    (Grid::new(rows, columns, obstacles), guards)
 }
 
 type Coordinate = i16;
 type Position = Point2D<Coordinate>;
+pub type Obstacle = char;
+
+/// The kind of obstacle placed when looking for loops
+pub const DEFAULT_OBSTACLE: Obstacle = '#';
 
 pub struct Grid {
     rows: usize,
     columns: usize,
-    obstacles: HashSet<Position>,
-    collisions_cache: RefCell<HashMap<Collision, Option<Collision>>>,
+    obstacles: HashMap<Position, Obstacle>,
+    obstacles_index: ObstacleIndex<Coordinate>,
+    turning_rule: Box<dyn TurningRule>,
 }
 
 impl Grid {
-    fn new(rows: usize, columns: usize, obstacles: HashSet<Position>) -> Self {
+    fn new(rows: usize, columns: usize, obstacles: HashMap<Position, Obstacle>) -> Self {
         Self {
             rows,
             columns,
+            obstacles_index: obstacles.keys().copied().collect(),
             obstacles,
-            collisions_cache: RefCell::new(HashMap::new()),
+            turning_rule: Box::new(Turn::Rotate(Orientation::Clockwise)),
         }
     }
 
-    pub fn unique_positions(&self, start: &Guard) -> HashSet<Position> {
-        self.iter_guard(start).map(|guard| guard.position).collect()
+    /// Replaces the rule guards follow when they bump into an obstacle. By
+    /// default they turn clockwise.
+    pub fn with_turning_rule<R: TurningRule + 'static>(self, turning_rule: R) -> Self {
+        Self {
+            turning_rule: Box::new(turning_rule),
+            ..self
+        }
+    }
+
+    /// Positions visited by any of the guards before they leave the grid, or
+    /// the first guard that never leaves it.
+    pub fn unique_positions(&self, guards: &[Guard]) -> Result<HashSet<Position>, StuckGuard> {
+        self.check_guards_leave(guards)?;
+
+        Ok(guards
+            .iter()
+            .flat_map(|guard| self.iter_guard(guard))
+            .map(|guard| guard.position)
+            .collect())
+    }
+
+    pub fn count_loops(&self, guards: &[Guard]) -> Result<usize, StuckGuard> {
+        Ok(self.find_loops(guards)?.len())
     }
 
-    pub fn count_loops(&self, start: Guard) -> usize {
-        let mut visited = HashSet::new();
-        let mut loops_detected = HashSet::new();
+    /// Finds every position where placing a single additional obstacle makes
+    /// at least one of the guards patrol forever. Each position maps to the
+    /// loop the first such guard gets stuck in. It only makes sense if every
+    /// guard leaves the grid as it is, otherwise the first one that does not
+    /// is returned.
+    pub fn find_loops(&self, guards: &[Guard]) -> Result<HashMap<Position, GuardLoop>, StuckGuard> {
+        self.check_guards_leave(guards)?;
+
+        // The obstacle cannot be placed where a guard is standing
+        let starts: HashSet<Position> = guards.iter().map(|guard| guard.position).collect();
+        let mut loops_detected = HashMap::new();
+
+        for start in guards {
+            let mut visited = HashSet::new();
+
+            for guard in self.iter_guard(start) {
+                if starts.contains(&guard.position) || visited.contains(&guard.position) {
+                    continue;
+                }
 
-        for guard in self.iter_guard(&start) {
-            if guard.position == start.position || visited.contains(&guard.position) {
-                continue;
+                visited.insert(guard.position);
+                if loops_detected.contains_key(&guard.position) {
+                    continue;
+                }
+
+                if let Some(guard_loop) = self.find_loop(guard) {
+                    loops_detected.insert(guard.position, guard_loop);
+                }
             }
+        }
 
-            visited.insert(guard.position);
-            if self.creates_loop(guard) {
-                loops_detected.insert(guard.position);
+        Ok(loops_detected)
+    }
+
+    fn check_guards_leave(&self, guards: &[Guard]) -> Result<(), StuckGuard> {
+        for guard in guards {
+            if let Some(guard_loop) = self.patrol_loop(guard) {
+                return Err(StuckGuard {
+                    guard: *guard,
+                    guard_loop,
+                });
             }
         }
 
-        loops_detected.len()
+        Ok(())
+    }
+
+    /// The loop the guard gets stuck in on the grid as it is, if it never
+    /// leaves. A collision leaves the guard where it is, only facing another
+    /// way, so the loop is entered right after the first of those in a lap.
+    fn patrol_loop(&self, guard: &Guard) -> Option<GuardLoop> {
+        let history = self.iter_guard(guard).detect_cycle_hashed().ok()?;
+        let Cycle { mu, lambda } = history.cycle;
+        let turns: Vec<usize> = (mu..mu + lambda)
+            .filter(|&idx| history.nth(idx as u64).position == history.nth(idx as u64 + 1).position)
+            .map(|idx| history.cycle.reduce_index(idx as u64 + 1))
+            .collect();
+
+        Some(GuardLoop {
+            entry: *history.nth(*turns.iter().min()? as u64),
+            length: turns.len(),
+        })
     }
 
     fn iter_guard<'a>(&'a self, guard: &Guard) -> GuardIter<'a> {
@@ -85,9 +160,13 @@ impl Grid {
         }
     }
 
-    fn creates_loop(&self, guard: Guard) -> bool {
+    /// Checks whether placing an obstacle right where the given guard is
+    /// standing (just before it actually gets there) traps it in a loop.
+    fn find_loop(&self, guard: Guard) -> Option<GuardLoop> {
+        let additional_obstacle = [guard.position];
         let extended_grid = ExtendedGrid {
             base_grid: self,
+            obstacles: self.obstacles_index.with_extra(&additional_obstacle),
             additional_obstacle: guard.position,
         };
         let initial_collision = Collision {
@@ -95,44 +174,25 @@ impl Grid {
             face: -guard.facing,
         };
         let collisions = extended_grid.iter_collisions(initial_collision);
+        let Cycle { mu, lambda } = collisions.detect_cycle()?;
+        let entry_collision = collisions.clone().nth(mu)?;
 
-        collisions.detect_cycle().is_some()
+        Some(GuardLoop {
+            entry: extended_grid.guard_after(entry_collision),
+            length: lambda,
+        })
     }
 
-    /// Returns the next obstacle the guard collides with, starting from the given obstacle and face direction.
-    fn next_collision(&self, collision: Collision) -> Option<Collision> {
-        // Check if the result is already cached
-        if let Some(&result) = self.collisions_cache.borrow().get(&collision) {
-            return result;
-        }
-
-        // Compute the result lazily
-        let result = self.compute_next_collision(Guard::from(collision));
-
-        // Cache the result
-        self.collisions_cache.borrow_mut().insert(collision, result);
-
-        result
+    /// The state of the guard right after turning away from the given collision
+    fn guard_after(&self, collision: Collision) -> Guard {
+        self.guard_after_obstacle(collision, self.obstacles[&collision.position])
     }
 
-    /// Computes the next obstacle collision
-    fn compute_next_collision(&self, guard: Guard) -> Option<Collision> {
-        let delta: Position = Position::from(guard.facing);
-        let mut current_position = guard.position + delta;
-
-        // Simulate the guard moving in the given direction until it hits an obstacle or exits the grid
-        while self.is_valid(&current_position) {
-            if self.obstacles.contains(&current_position) {
-                return Some(Collision {
-                    position: current_position,
-                    face: -guard.facing,
-                });
-            }
-            current_position = current_position + delta;
+    fn guard_after_obstacle(&self, collision: Collision, obstacle: Obstacle) -> Guard {
+        Guard {
+            position: collision.position + Position::from(collision.face),
+            facing: self.turning_rule.turn(-collision.face, obstacle),
         }
-
-        // No obstacle collision; guard exits the grid
-        None
     }
 
     fn is_valid(&self, position: &Position) -> bool {
@@ -141,45 +201,91 @@ impl Grid {
             && position.y >= 0
             && position.y < self.rows as Coordinate
     }
+}
+
+/// Decides which way a guard faces after bumping into an obstacle
+pub trait TurningRule {
+    fn turn(&self, facing: Direction, obstacle: Obstacle) -> Direction;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Turn {
+    Rotate(Orientation),
+    Reverse,
+}
+
+impl TurningRule for Turn {
+    fn turn(&self, facing: Direction, _: Obstacle) -> Direction {
+        match self {
+            Turn::Rotate(orientation) => facing.rotate(*orientation),
+            Turn::Reverse => -facing,
+        }
+    }
+}
 
-    fn is_empty(&self, position: &Position) -> bool {
-        !self.obstacles.contains(position)
+/// Per obstacle kind rule. Obstacles missing from the map make the guard turn
+/// clockwise, as in the original puzzle.
+impl TurningRule for HashMap<Obstacle, Turn> {
+    fn turn(&self, facing: Direction, obstacle: Obstacle) -> Direction {
+        self.get(&obstacle)
+            .unwrap_or(&Turn::Rotate(Orientation::Clockwise))
+            .turn(facing, obstacle)
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Guard {
-    position: Position,
-    facing: Direction,
+    pub position: Position,
+    pub facing: Direction,
 }
 
 impl Guard {
+    pub fn new(position: Position, facing: Direction) -> Self {
+        Self { position, facing }
+    }
+
     fn advance(&self, grid: &Grid) -> Option<Guard> {
         let delta: Position = Position::from(self.facing);
         let candidate_next = self.position + delta;
 
         if !grid.is_valid(&candidate_next) {
-            return None;
-        } else if !grid.is_empty(&candidate_next) {
-            return Some(Self {
+            None
+        } else if let Some(&obstacle) = grid.obstacles.get(&candidate_next) {
+            Some(Self {
                 position: self.position,
-                facing: self.facing.rotate(Orientation::Clockwise),
-            });
+                facing: grid.turning_rule.turn(self.facing, obstacle),
+            })
         } else {
-            return Some(Self {
+            Some(Self {
                 position: candidate_next,
                 facing: self.facing,
-            });
+            })
         }
     }
 }
 
+/// A loop a guard gets stuck in. The entry is the first state of the guard
+/// that is part of the loop, right after turning away from an obstacle, and
+/// the length is the number of collisions in a full lap.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GuardLoop {
+    pub entry: Guard,
+    pub length: usize,
+}
+
+/// A guard that patrols forever on the grid as it is, with its loop
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StuckGuard {
+    pub guard: Guard,
+    pub guard_loop: GuardLoop,
+}
+
 struct GuardIter<'a> {
-    guard: Option<Guard>, // TODO: make this Option to ensure the iterator is fused
+    guard: Option<Guard>,
     grid: &'a Grid,
 }
 
-impl<'a> Iterator for GuardIter<'a> {
+impl Iterator for GuardIter<'_> {
     type Item = Guard;
     fn next(&mut self) -> Option<Self::Item> {
         let current = self.guard;
@@ -192,55 +298,37 @@ impl<'a> Iterator for GuardIter<'a> {
 /// A wrapper over a Grid on which an additional obstacle has been placed
 struct ExtendedGrid<'a> {
     base_grid: &'a Grid,
+    obstacles: ExtendedObstacleIndex<'a, Coordinate>,
     additional_obstacle: Position,
 }
 
 impl<'a> ExtendedGrid<'a> {
     fn iter_collisions(&'a self, collision: Collision) -> CollisionsIter<'a> {
         CollisionsIter {
-            extended_grid: &self,
+            extended_grid: self,
             collision: Some(collision),
         }
     }
 
+    /// Returns the next obstacle the guard collides with, starting from the given obstacle and face direction.
     fn next_collision(&self, collision: Collision) -> Option<Collision> {
-        let guard = Guard::from(collision);
-        let base_result = self.base_grid.next_collision(collision);
-
-        // Guard cannot possibly bump into the additional obstacle so we can
-        // return the base result as is
-        if !self.may_collision_with_additional(guard) {
-            return base_result;
-        }
-
-        Some(
-            base_result
-                // If the base result is NOT between the guard and the
-                // additional obstacle then the guard will bump into the
-                // additional obstacle first
-                .filter(|next_collision| {
-                    next_collision
-                        .position
-                        .is_between(&guard.position, &self.additional_obstacle)
-                })
-                // If the guard would not normally bump into any obstacles, or
-                // it has been filtered out in the previous step, then the
-                // collision is with the additional obstacle
-                .unwrap_or(Collision {
-                    position: self.additional_obstacle,
-                    face: -guard.facing,
-                }),
-        )
+        let guard = self.guard_after(collision);
+
+        self.obstacles
+            .next_obstacle(guard.position, guard.facing)
+            .map(|position| Collision {
+                position,
+                face: -guard.facing,
+            })
     }
 
-    /// Whether the given guard may end up bumping into the additional obstacle.
-    /// This does not mean it necessarily will, because it might be stopped by
-    /// an earlier obstacle.
-    fn may_collision_with_additional(&self, guard: Guard) -> bool {
-        let step = Position::from(guard.facing);
-        let delta = self.additional_obstacle - guard.position;
-        delta.is_parallel(&step) // guard and obstacle are in the same axis
-            && delta.dot(&step) > 0 // guard is facing towards the obstacle, not away from it
+    fn guard_after(&self, collision: Collision) -> Guard {
+        if collision.position == self.additional_obstacle {
+            self.base_grid
+                .guard_after_obstacle(collision, DEFAULT_OBSTACLE)
+        } else {
+            self.base_grid.guard_after(collision)
+        }
     }
 }
 
@@ -250,7 +338,7 @@ struct CollisionsIter<'a> {
     collision: Option<Collision>,
 }
 
-impl<'a> Iterator for CollisionsIter<'a> {
+impl Iterator for CollisionsIter<'_> {
     type Item = Collision;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -269,11 +357,88 @@ struct Collision {
     face: Direction,
 }
 
-impl From<Collision> for Guard {
-    fn from(collision: Collision) -> Self {
-        Self {
-            position: collision.position + Position::from(collision.face),
-            facing: collision.face.rotate(Orientation::Counterclockwise),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_unique_positions() {
+        let input = fs::read_to_string("data/day6/test_input").unwrap();
+        let (grid, guards) = parse_input(&input);
+
+        assert_eq!(vec![Guard::new(Position::new(4, 3), Direction::Up)], guards);
+        assert_eq!(41, grid.unique_positions(&guards).unwrap().len());
+    }
+
+    #[test]
+    fn test_find_loops() {
+        let input = fs::read_to_string("data/day6/test_input").unwrap();
+        let (grid, guards) = parse_input(&input);
+        let loops = grid.find_loops(&guards).unwrap();
+        let expected_obstacles = HashSet::from([
+            Position::new(3, 3),
+            Position::new(6, 2),
+            Position::new(7, 2),
+            Position::new(1, 1),
+            Position::new(3, 1),
+            Position::new(7, 0),
+        ]);
+
+        assert_eq!(expected_obstacles, loops.keys().copied().collect());
+        // Turning clockwise, a loop needs at least one collision per direction
+        assert!(loops.values().all(|guard_loop| guard_loop.length >= 4));
+    }
+
+    #[test]
+    fn test_reverse_turning_rule() {
+        let input = "..#..\n.....\n..^..\n.....\n";
+        let (grid, guards) = parse_input(input);
+        let grid = grid.with_turning_rule(Turn::Reverse);
+
+        // The guard bounces back down and leaves the grid
+        assert_eq!(3, grid.unique_positions(&guards).unwrap().len());
+        // Any obstacle below the guard traps it between it and the top obstacle
+        assert_eq!(
+            HashSet::from([Position::new(2, 0)]),
+            grid.find_loops(&guards).unwrap().keys().copied().collect()
+        );
+    }
+
+    #[test]
+    fn test_obstacle_turning_rule() {
+        let input = "..#..\n.....\n..^..\n..L.<\n";
+        let (grid, guards) = parse_input(input);
+        let grid = grid.with_turning_rule(HashMap::from([(
+            'L',
+            Turn::Rotate(Orientation::Counterclockwise),
+        )]));
+
+        assert_eq!(2, guards.len());
+        // The first guard turns right at the top and leaves, and the second
+        // one turns left at the L and leaves right away instead of going up
+        let positions = grid.unique_positions(&guards).unwrap();
+        assert!(positions.contains(&Position::new(4, 2)));
+        assert!(positions.contains(&Position::new(3, 0)));
+        assert!(!positions.contains(&Position::new(3, 1)));
+    }
+
+    #[test]
+    fn test_loop_without_obstacle() {
+        let (grid, guards) = parse_input(".#.\n...\n.^.\n.#.\n");
+        let grid = grid.with_turning_rule(Turn::Reverse);
+        // Bouncing between the two obstacles, back where it started after
+        // turning at the bottom one
+        let stuck = StuckGuard {
+            guard: guards[0],
+            guard_loop: GuardLoop {
+                entry: Guard::new(Position::new(1, 1), Direction::Up),
+                length: 2,
+            },
+        };
+
+        assert_eq!(Err(stuck), grid.unique_positions(&guards));
+        assert_eq!(Err(stuck), grid.find_loops(&guards));
+        assert_eq!(Err(stuck), grid.count_loops(&guards));
     }
 }
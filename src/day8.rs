@@ -1,13 +1,15 @@
 use crate::common::pairs;
-use crate::spatial::Point2D;
+use crate::spatial::{Point2D, Rectangle};
 use gcd::Gcd;
 use std::collections::{HashMap, HashSet};
 use std::iter::successors;
 use std::str::FromStr;
 
 type Frequency = char;
-type Coordinate = i16;
+// Wide enough for windows far bigger than the map
+type Coordinate = i64;
 type Position = Point2D<Coordinate>;
+pub type Window = Rectangle<Coordinate>;
 
 pub struct Grid {
     rows: usize,
@@ -15,68 +17,330 @@ pub struct Grid {
     antennas: HashMap<Frequency, Vec<Position>>,
 }
 
+/// Decides where a pair of antennas creates antinodes
+pub trait AntinodeRule {
+    /// Antinodes of the given antennas that lie inside the window
+    fn antinodes(&self, first: Position, second: Position, window: &Window) -> Vec<Position>;
+
+    /// Whether antennas of the given frequencies interact at all. By default
+    /// only antennas of the same frequency do.
+    fn resonates(&self, first: Frequency, second: Frequency) -> bool {
+        first == second
+    }
+}
+
+/// Points in line with both antennas, where one of them is k times as far
+/// as the other one. With k = 2 this is the original rule: one antinode on
+/// the outer side of each antenna, and two more between them when they fall
+/// on the grid.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DistanceRatio {
+    pub k: u16,
+}
+
+impl AntinodeRule for DistanceRatio {
+    fn antinodes(&self, first: Position, second: Position, window: &Window) -> Vec<Position> {
+        let delta = second - first;
+        let k = self.k as Coordinate;
+
+        // Antinodes are at first + t * delta, where |t| = k * |t - 1| or
+        // |t - 1| = k * |t|, with t as a fraction
+        let mut fractions = vec![(k, k + 1), (1, k + 1)];
+        if k != 1 {
+            fractions.extend([(k, k - 1), (-1, k - 1)]);
+        }
+
+        fractions
+            .into_iter()
+            .filter(|&(_, denominator)| delta.x % denominator == 0 && delta.y % denominator == 0)
+            .map(|(numerator, denominator)| {
+                first + Position::new(delta.x / denominator, delta.y / denominator) * numerator
+            })
+            .filter(|antinode| window.contains(antinode))
+            .collect()
+    }
+}
+
+/// Points in line with both antennas. With no limit, that is every point of
+/// the line, as in the updated model. With a limit, only the points at a
+/// whole multiple of the distance between the antennas, up to that many
+/// times away from the closest antenna, which includes the antennas.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Harmonics {
+    pub max_multiple: Option<usize>,
+}
+
+impl AntinodeRule for Harmonics {
+    fn antinodes(&self, first: Position, second: Position, window: &Window) -> Vec<Position> {
+        let delta = second - first;
+
+        let (start, step) = match self.max_multiple {
+            None => {
+                let gcd = (delta.x.unsigned_abs()).gcd(delta.y.unsigned_abs()) as Coordinate;
+                (first, Position::new(delta.x / gcd, delta.y / gcd))
+            }
+            Some(max_multiple) => {
+                let forwards = successors(Some(second), |&current| Some(current + delta));
+                let backwards = successors(Some(first), |&current| Some(current - delta));
+
+                return forwards
+                    .take(max_multiple + 1)
+                    .take_while(|antinode| window.contains(antinode))
+                    .chain(
+                        backwards
+                            .take(max_multiple + 1)
+                            .take_while(|antinode| window.contains(antinode)),
+                    )
+                    .collect();
+            }
+        };
+
+        Grid::generate_ray(start, step, window)
+    }
+}
+
+/// Lets antennas of different frequencies interact too, following the same
+/// rule as antennas of the same frequency.
+pub struct CrossFrequency<R> {
+    pub rule: R,
+    /// Pairs of frequencies that interact, in any order
+    pub frequencies: Vec<(Frequency, Frequency)>,
+}
+
+impl<R: AntinodeRule> AntinodeRule for CrossFrequency<R> {
+    fn antinodes(&self, first: Position, second: Position, window: &Window) -> Vec<Position> {
+        self.rule.antinodes(first, second, window)
+    }
+
+    fn resonates(&self, first: Frequency, second: Frequency) -> bool {
+        self.rule.resonates(first, second)
+            || self
+                .frequencies
+                .iter()
+                .any(|&pair| pair == (first, second) || pair == (second, first))
+    }
+}
+
+/// A pair of antennas that creates an antinode
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct AntinodeSource {
+    pub frequencies: (Frequency, Frequency),
+    pub antennas: (Position, Position),
+}
+
 impl Grid {
     pub fn unique_antinodes(&self) -> HashSet<Position> {
-        self.antennas
-            .iter()
-            .flat_map(|(_, positions)| {
-                pairs(positions)
-                    .into_iter()
-                    .flat_map(|(first, second)| self.generate_antinodes(first, second))
-            })
-            .collect::<HashSet<Position>>()
+        self.antinodes(&DistanceRatio { k: 2 })
     }
 
     pub fn unique_harmonic_antinodes(&self) -> HashSet<Position> {
-        self.antennas
+        self.antinodes(&Harmonics { max_multiple: None })
+    }
+
+    pub fn antinodes<R: AntinodeRule>(&self, rule: &R) -> HashSet<Position> {
+        self.antinodes_in(rule, &self.window())
+    }
+
+    /// Antinodes inside any window, which may go well beyond the map
+    pub fn antinodes_in<R: AntinodeRule>(&self, rule: &R, window: &Window) -> HashSet<Position> {
+        self.antinode_sources_in(rule, window).into_keys().collect()
+    }
+
+    /// Every antinode on the grid, with the antenna pairs that create it
+    pub fn antinode_sources<R: AntinodeRule>(
+        &self,
+        rule: &R,
+    ) -> HashMap<Position, Vec<AntinodeSource>> {
+        self.antinode_sources_in(rule, &self.window())
+    }
+
+    pub fn antinode_sources_in<R: AntinodeRule>(
+        &self,
+        rule: &R,
+        window: &Window,
+    ) -> HashMap<Position, Vec<AntinodeSource>> {
+        let mut antennas: Vec<(Frequency, Position)> = self
+            .antennas
             .iter()
-            .flat_map(|(_, positions)| {
-                pairs(positions)
-                    .into_iter()
-                    .flat_map(|(first, second)| self.generate_harmonic_antinodes(first, second))
+            .flat_map(|(&frequency, positions)| {
+                positions.iter().map(move |&position| (frequency, position))
             })
-            .collect::<HashSet<Position>>()
-    }
+            .collect();
+        // Sorted so pairs always come in the same order
+        antennas.sort();
+        let mut sources: HashMap<Position, Vec<AntinodeSource>> = HashMap::new();
 
-    fn generate_antinodes(&self, first: Position, second: Position) -> Vec<Position> {
-        let delta = second - first;
-        let mut antinodes = vec![first - delta, second + delta];
+        for ((first_frequency, first), (second_frequency, second)) in pairs(&antennas) {
+            if !rule.resonates(first_frequency, second_frequency) {
+                continue;
+            }
 
-        if delta.x % 3 == 0 && delta.y % 3 == 0 {
-            let small_delta = Position::new(delta.x / 3, delta.y / 3);
-            antinodes.push(first + small_delta);
-            antinodes.push(second - small_delta);
+            for antinode in rule.antinodes(first, second, window) {
+                sources.entry(antinode).or_default().push(AntinodeSource {
+                    frequencies: (first_frequency, second_frequency),
+                    antennas: (first, second),
+                });
+            }
         }
 
-        antinodes
+        sources
+    }
+
+    /// Antinodes created by antennas of the given frequency, with the pairs
+    /// of antennas that create each of them
+    pub fn antinodes_of<R: AntinodeRule>(
+        &self,
+        frequency: Frequency,
+        rule: &R,
+    ) -> HashMap<Position, Vec<(Position, Position)>> {
+        self.antinode_sources(rule)
             .into_iter()
-            .filter(|antinode| self.is_valid(antinode))
+            .filter_map(|(antinode, sources)| {
+                let pairs: Vec<_> = sources
+                    .into_iter()
+                    .filter(|source| {
+                        source.frequencies.0 == frequency || source.frequencies.1 == frequency
+                    })
+                    .map(|source| source.antennas)
+                    .collect();
+                Some((antinode, pairs)).filter(|(_, pairs)| !pairs.is_empty())
+            })
             .collect()
     }
 
-    fn generate_harmonic_antinodes(&self, first: Position, second: Position) -> Vec<Position> {
-        let delta = second - first;
-        let gcd = (delta.x.abs() as usize).gcd(delta.y.abs() as usize) as Coordinate;
-        let small_delta = Position::new(delta.x / gcd, delta.y / gcd);
-        self.generate_ray(first, small_delta)
+    /// Number of antinodes inside the window under the unlimited harmonics
+    /// rule, without listing them, so it works on windows of any size. Each
+    /// line through two antennas holds a number of points that follows from
+    /// its step, and points where lines cross are only counted once.
+    pub fn count_harmonic_antinodes(&self, window: &Window) -> u64 {
+        let lines: Vec<Line> = self
+            .antennas
+            .values()
+            .flat_map(|positions| pairs(positions))
+            .map(|(first, second)| Line::through(first, second))
+            .collect::<HashSet<Line>>()
+            .into_iter()
+            .collect();
+
+        // How many lines go through each crossing inside the window
+        let mut crossings: HashMap<Position, u64> = HashMap::new();
+        for (idx, first) in lines.iter().enumerate() {
+            for second in lines[idx + 1..].iter() {
+                if let Some(crossing) = first.crossing(second) {
+                    if window.contains(&crossing) {
+                        *crossings.entry(crossing).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        let on_lines: u64 = lines.iter().map(|line| line.count_in(window)).sum();
+        // A point on m lines is counted m times, with m(m - 1)/2 crossings
+        let repeated: u64 = crossings
+            .into_values()
+            .map(|pairs| {
+                // Solving pairs = m(m - 1)/2 for m gives m = (1 + sqrt(1 + 8 pairs))/2,
+                // and the square root is exact as pairs is a triangular number
+                let lines = (1 + 8 * pairs).isqrt().div_ceil(2);
+                lines - 1
+            })
+            .sum();
+
+        on_lines - repeated
     }
 
-    fn generate_ray(&self, start: Position, delta: Position) -> Vec<Position> {
+    pub fn window(&self) -> Window {
+        Window {
+            min: Position::new(0, 0),
+            max: Position::new(self.columns as Coordinate - 1, self.rows as Coordinate - 1),
+        }
+    }
+
+    fn generate_ray(start: Position, delta: Position, window: &Window) -> Vec<Position> {
         let forwards = successors(Some(start), |&current| {
-            Some(current + delta).filter(|candidate| self.is_valid(candidate))
+            Some(current + delta).filter(|candidate| window.contains(candidate))
         });
         let backwards = successors(Some(start), |&current| {
-            Some(current - delta).filter(|candidate| self.is_valid(candidate))
+            Some(current - delta).filter(|candidate| window.contains(candidate))
         });
 
-        forwards.chain(backwards).collect()
+        forwards.chain(backwards.skip(1)).collect()
+    }
+}
+
+/// A line through lattice points, as the point closest to the origin along
+/// it and the smallest step between its points, pointing right or up
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+struct Line {
+    origin: Position,
+    step: Position,
+}
+
+impl Line {
+    fn through(first: Position, second: Position) -> Self {
+        let delta = second - first;
+        let gcd = (delta.x.unsigned_abs()).gcd(delta.y.unsigned_abs()) as Coordinate;
+        let mut step = Position::new(delta.x / gcd, delta.y / gcd);
+        if step.x < 0 || (step.x == 0 && step.y < 0) {
+            step = Position::new(-step.x, -step.y);
+        }
+
+        // Same line, same representation: take the point whose projection
+        // on the step is closest to zero, i.e. t = -(p . step) / |step|^2
+        let squared_norm = step.dot(&step);
+        let t = (-first.dot(&step)).div_euclid(squared_norm);
+        let origin = first + step * t;
+
+        Self { origin, step }
+    }
+
+    /// The lattice point both lines go through, if any
+    fn crossing(&self, other: &Line) -> Option<Position> {
+        let cross =
+            |a: Position, b: Position| a.x as i128 * b.y as i128 - a.y as i128 * b.x as i128;
+        let denominator = cross(self.step, other.step);
+        if denominator == 0 {
+            return None; // parallel
+        }
+
+        // self.origin + t * self.step = other.origin + s * other.step
+        let numerator = cross(other.origin - self.origin, other.step);
+        if numerator % denominator != 0 {
+            return None; // crossing between lattice points
+        }
+
+        let t = (numerator / denominator) as Coordinate;
+        Some(self.origin + self.step * t)
     }
 
-    fn is_valid(&self, antinode: &Position) -> bool {
-        antinode.x >= 0
-            && antinode.x < self.columns as Coordinate
-            && antinode.y >= 0
-            && antinode.y < self.rows as Coordinate
+    /// Number of points of the line inside the window, found as the range of
+    /// multiples of the step that keeps both coordinates inside it
+    fn count_in(&self, window: &Window) -> u64 {
+        let mut low = Coordinate::MIN;
+        let mut high = Coordinate::MAX;
+
+        for (start, step, min, max) in [
+            (self.origin.x, self.step.x, window.min.x, window.max.x),
+            (self.origin.y, self.step.y, window.min.y, window.max.y),
+        ] {
+            if step == 0 {
+                if start < min || start > max {
+                    return 0;
+                }
+                continue;
+            }
+
+            // min <= start + t * step <= max, with step > 0 after flipping
+            let (step, min, max) = if step > 0 {
+                (step, min - start, max - start)
+            } else {
+                (-step, start - max, start - min)
+            };
+            low = low.max(min.div_euclid(step) + (min.rem_euclid(step) != 0) as Coordinate);
+            high = high.min(max.div_euclid(step));
+        }
+
+        (high - low + 1).max(0) as u64
     }
 }
 
@@ -105,3 +369,195 @@ impl FromStr for Grid {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Rng;
+    use std::fs;
+
+    fn test_grid() -> Grid {
+        fs::read_to_string("data/day8/test_input")
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_unique_antinodes() {
+        let grid = test_grid();
+
+        assert_eq!(14, grid.unique_antinodes().len());
+        assert_eq!(34, grid.unique_harmonic_antinodes().len());
+    }
+
+    #[test]
+    fn test_distance_ratio() {
+        let window = Window {
+            min: Position::new(-20, -20),
+            max: Position::new(20, 20),
+        };
+        let first = Position::new(0, 0);
+        let second = Position::new(8, 4);
+        let antinodes = |k| -> HashSet<Position> {
+            DistanceRatio { k }
+                .antinodes(first, second, &window)
+                .into_iter()
+                .collect()
+        };
+
+        assert_eq!(
+            HashSet::from([
+                Position::new(-4, -2),
+                Position::new(12, 6),
+                Position::new(6, 3),
+                Position::new(2, 1),
+            ]),
+            antinodes(3)
+        );
+        // With k = 3 they are at -1/2 and 3/2 of the way, and the inner
+        // ones at 1/4 and 3/4. Only the outer ones fall on the grid with
+        // k = 2, at -1 and 2 times the way, as thirds of (8, 4) are not whole
+        assert_eq!(
+            HashSet::from([Position::new(-8, -4), Position::new(16, 8)]),
+            antinodes(2)
+        );
+        // The midpoint is the only point equally far from both
+        assert_eq!(HashSet::from([Position::new(4, 2)]), antinodes(1));
+    }
+
+    #[test]
+    fn test_harmonics() {
+        let grid = test_grid();
+        let antennas: usize = grid.antennas.values().map(Vec::len).sum();
+
+        // Zero multiples away leaves only the antennas themselves
+        assert_eq!(
+            antennas,
+            grid.antinodes(&Harmonics {
+                max_multiple: Some(0)
+            })
+            .len()
+        );
+        // Unlimited multiples on this grid go as far as the unlimited rule
+        assert_eq!(
+            34,
+            grid.antinodes(&Harmonics {
+                max_multiple: Some(100)
+            })
+            .len()
+        );
+    }
+
+    #[test]
+    fn test_cross_frequency() {
+        let grid: Grid =
+            "..........\n..........\n...a......\n..........\n....b.....\n..........\n..........\n"
+                .parse()
+                .unwrap();
+        assert!(grid.unique_antinodes().is_empty());
+
+        let rule = CrossFrequency {
+            rule: DistanceRatio { k: 2 },
+            frequencies: vec![('b', 'a')],
+        };
+        let sources = grid.antinode_sources(&rule);
+
+        assert_eq!(
+            HashSet::from([Position::new(2, 6), Position::new(5, 0)]),
+            sources.keys().copied().collect()
+        );
+        assert!(sources.values().flatten().all(|source| {
+            source.frequencies == ('a', 'b')
+                && source.antennas == (Position::new(3, 4), Position::new(4, 2))
+        }));
+    }
+
+    #[test]
+    fn test_bigger_windows() {
+        let grid = test_grid();
+        let rule = Harmonics { max_multiple: None };
+
+        assert_eq!(34, grid.count_harmonic_antinodes(&grid.window()));
+        for margin in [1, 5, 20, 60] {
+            let window = grid.window().grown(margin);
+            let listed = grid.antinodes_in(&rule, &window);
+
+            assert!(listed.iter().all(|antinode| window.contains(antinode)));
+            assert_eq!(listed.len() as u64, grid.count_harmonic_antinodes(&window));
+        }
+
+        // The original rule only ever has a few antinodes per pair: with the
+        // whole plane in sight, the two outer ones of each of the 9 pairs,
+        // one of them shared, and no pair a multiple of 3 apart for inner ones
+        let far = grid.antinodes_in(&DistanceRatio { k: 2 }, &grid.window().grown(1000));
+        assert_eq!(17, far.len());
+    }
+
+    #[test]
+    fn test_count_random_grids() {
+        // Three lines through the centre, which is counted once
+        let grid: Grid = "acb\n...\nbca\n".parse().unwrap();
+        assert_eq!(7, grid.count_harmonic_antinodes(&grid.window()));
+
+        let mut rng = Rng::new(5);
+        let mut next = move |bound: u64| rng.below(bound);
+
+        for _ in 0..50 {
+            let input: String = (0..10)
+                .map(|_| {
+                    let row: String = (0..10)
+                        .map(|_| match next(30) {
+                            0 => 'a',
+                            1 => 'b',
+                            _ => '.',
+                        })
+                        .collect();
+                    row + "\n"
+                })
+                .collect();
+            let grid: Grid = input.parse().unwrap();
+            let window = grid.window().grown(next(15) as Coordinate);
+
+            assert_eq!(
+                grid.antinodes_in(&Harmonics { max_multiple: None }, &window)
+                    .len() as u64,
+                grid.count_harmonic_antinodes(&window)
+            );
+        }
+    }
+
+    #[test]
+    fn test_huge_window() {
+        let grid: Grid = "...\n.a.\n...\n..a\n".parse().unwrap();
+        let window = Window {
+            min: Position::new(-1_000_000_000, -1_000_000_000),
+            max: Position::new(1_000_000_000, 1_000_000_000),
+        };
+
+        // Antennas at (1, 2) and (2, 0), a line with a step of (1, -2), so
+        // the y coordinate limits it to 10^9 points plus the one at y = 0
+        assert_eq!(1_000_000_001, grid.count_harmonic_antinodes(&window));
+    }
+
+    #[test]
+    fn test_antinodes_of() {
+        let grid = test_grid();
+        let rule = DistanceRatio { k: 2 };
+        let zeros = grid.antinodes_of('0', &rule);
+        let capital_as = grid.antinodes_of('A', &rule);
+
+        let all: HashSet<Position> = zeros.keys().chain(capital_as.keys()).copied().collect();
+        assert_eq!(grid.unique_antinodes(), all);
+        // Antinodes of a frequency only come from its own antennas
+        for (antinode, pairs) in zeros {
+            for (first, second) in pairs {
+                assert!(grid.antennas[&'0'].contains(&first));
+                assert!(grid.antennas[&'0'].contains(&second));
+                assert!(rule
+                    .antinodes(first, second, &grid.window())
+                    .contains(&antinode));
+            }
+        }
+    }
+}
@@ -1,12 +1,271 @@
+use std::cmp::{max, min};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::ops::Range;
 use std::str::FromStr;
 
 pub type DiskRange = Range<usize>;
+
 pub trait FileCompactor {
-    fn checksum(&self) -> usize;
-    fn compact_file(&self, file: &File, gaps: &mut Vec<Gap>) -> Vec<DiskRange>;
+    /// Where the blocks of every file end up, as a list of fragments
+    fn compact(&self, disk: &Disk) -> Vec<File>;
+}
+
+/// Compactors that move one file at a time, from the end of the disk
+/// backwards, into the free space to the left of it.
+pub trait FileByFileCompactor {
+    /// Decides where the blocks of the given file end up, claiming them from
+    /// the free space.
+    fn compact_file(&self, file: &File, free_space: &mut FreeSpace) -> Vec<DiskRange>;
+}
+
+impl<T: FileByFileCompactor> FileCompactor for T {
+    fn compact(&self, disk: &Disk) -> Vec<File> {
+        let mut free_space = FreeSpace::new(disk.gaps());
+
+        disk.files
+            .iter()
+            .rev()
+            .flat_map(|file| {
+                self.compact_file(file, &mut free_space)
+                    .into_iter()
+                    .map(|location| File {
+                        id: file.id,
+                        location,
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Moves files block by block into the leftmost free blocks
+pub struct BlockFileCompactor;
+
+impl FileByFileCompactor for BlockFileCompactor {
+    fn compact_file(&self, file: &File, free_space: &mut FreeSpace) -> Vec<DiskRange> {
+        let mut ranges = vec![];
+        let mut total_moved = 0;
+
+        while total_moved < file.size() {
+            // A gap to the right of the file original location is no use
+            let Some(idx) = free_space.leftmost_fit(1, file.location.start) else {
+                break;
+            };
+
+            let to_move = min(file.size() - total_moved, free_space.gap(idx).0.len());
+            ranges.push(free_space.claim(idx, to_move));
+            total_moved += to_move;
+        }
+
+        if total_moved < file.size() {
+            ranges.push(file.location.start..file.location.start + file.size() - total_moved);
+        }
+
+        ranges
+    }
+}
+
+/// Moves whole files into the leftmost gap they fit in
+pub struct WholeFileCompactor;
+
+impl FileByFileCompactor for WholeFileCompactor {
+    fn compact_file(&self, file: &File, free_space: &mut FreeSpace) -> Vec<DiskRange> {
+        let target = free_space.leftmost_fit(file.size(), file.location.start);
+        move_whole_file(file, free_space, target)
+    }
+}
+
+/// Moves whole files into the smallest gap they fit in
+pub struct BestFitCompactor;
+
+impl FileByFileCompactor for BestFitCompactor {
+    fn compact_file(&self, file: &File, free_space: &mut FreeSpace) -> Vec<DiskRange> {
+        let target = free_space.best_fit(file.size(), file.location.start);
+        move_whole_file(file, free_space, target)
+    }
+}
+
+/// Moves whole files into the largest gap to their left
+pub struct WorstFitCompactor;
+
+impl FileByFileCompactor for WorstFitCompactor {
+    fn compact_file(&self, file: &File, free_space: &mut FreeSpace) -> Vec<DiskRange> {
+        let target = free_space.worst_fit(file.size(), file.location.start);
+        move_whole_file(file, free_space, target)
+    }
+}
+
+fn move_whole_file(
+    file: &File,
+    free_space: &mut FreeSpace,
+    target: Option<usize>,
+) -> Vec<DiskRange> {
+    match target {
+        Some(idx) if file.size() > 0 => vec![free_space.claim(idx, file.size())],
+        _ => vec![file.location.clone()],
+    }
+}
+
+/// Rewrites the disk with every file in a single piece and no gaps at all,
+/// keeping the files in the order they first show up.
+pub struct DefragmentCompactor;
+
+impl FileCompactor for DefragmentCompactor {
+    fn compact(&self, disk: &Disk) -> Vec<File> {
+        let mut order = vec![];
+        let mut sizes: HashMap<usize, usize> = HashMap::new();
+
+        for file in disk.files.iter() {
+            let size = sizes.entry(file.id).or_insert_with(|| {
+                order.push(file.id);
+                0
+            });
+            *size += file.size();
+        }
+
+        let mut start = 0;
+        order
+            .into_iter()
+            .map(|id| {
+                let location = start..start + sizes[&id];
+                start = location.end;
+                File { id, location }
+            })
+            .collect()
+    }
 }
 
+/// Tries to make the disk short without splitting any file, by running best
+/// fit passes until no file moves or the passes run out. Each pass starts
+/// from a free space index of the disk left by the previous one, so files can
+/// take the space that others left behind. This is not optimal: finding the
+/// shortest disk is a bin packing problem, and best fit can fill a gap with
+/// a file that would have been better somewhere else. ShortestDiskCompactor
+/// finds the shortest one, but only small disks can afford it.
+pub struct RepeatedBestFitCompactor {
+    pub max_passes: usize,
+}
+
+impl Default for RepeatedBestFitCompactor {
+    fn default() -> Self {
+        Self { max_passes: 10 }
+    }
+}
+
+impl FileCompactor for RepeatedBestFitCompactor {
+    fn compact(&self, disk: &Disk) -> Vec<File> {
+        let mut current = disk.clone();
+
+        for _ in 0..self.max_passes {
+            let compacted = current.compacted(&BestFitCompactor);
+            if compacted == current {
+                break;
+            }
+            current = compacted;
+        }
+
+        current.files
+    }
+}
+
+/// Makes the disk as short as it can be by moving whole files, one at a
+/// time, into free space to their left. Every order of moves is searched,
+/// as an early move may be what makes room for a later one, so it is meant
+/// for small disks: the number of layouts grows exponentially with the
+/// files. The search stops early once the files leave no gap between them,
+/// or after max_layouts layouts, keeping the shortest one seen so far; only
+/// a search that gets to the end of the layouts is sure to be optimal.
+pub struct ShortestDiskCompactor {
+    pub max_layouts: usize,
+}
+
+impl Default for ShortestDiskCompactor {
+    fn default() -> Self {
+        Self {
+            max_layouts: 1_000_000,
+        }
+    }
+}
+
+impl ShortestDiskCompactor {
+    fn extent(files: &[File]) -> usize {
+        files.last().map(|file| file.location.end).unwrap_or(0)
+    }
+
+    /// Every layout reached by moving a single file, with files kept in
+    /// disk order
+    fn moves(files: &[File]) -> Vec<Vec<File>> {
+        let mut free = vec![];
+        let mut previous_end = 0;
+        for file in files {
+            free.push(previous_end..file.location.start);
+            previous_end = file.location.end;
+        }
+
+        let mut layouts = vec![];
+        for (idx, file) in files.iter().enumerate() {
+            // Only the free space before the file is of any use
+            for gap in free[..=idx].iter().filter(|gap| gap.len() >= file.size()) {
+                for start in gap.start..=gap.end - file.size() {
+                    let mut layout = files.to_vec();
+                    layout[idx].location = start..start + file.size();
+                    layout.sort_by_key(|file| file.location.start);
+                    layouts.push(layout);
+                }
+            }
+        }
+
+        layouts
+    }
+}
+
+impl FileCompactor for ShortestDiskCompactor {
+    fn compact(&self, disk: &Disk) -> Vec<File> {
+        let files: Vec<File> = disk
+            .files
+            .iter()
+            .filter(|file| file.size() > 0)
+            .cloned()
+            .collect();
+        let shortest_possible: usize = files.iter().map(File::size).sum();
+
+        let mut shortest = files.clone();
+        let mut seen = HashSet::from([files.clone()]);
+        let mut pending = vec![files];
+
+        while let Some(files) = pending.pop() {
+            if Self::extent(&files) < Self::extent(&shortest) {
+                shortest = files.clone();
+            }
+            if Self::extent(&shortest) == shortest_possible || seen.len() >= self.max_layouts {
+                break;
+            }
+
+            for layout in Self::moves(&files) {
+                if seen.insert(layout.clone()) {
+                    pending.push(layout);
+                }
+            }
+        }
+
+        shortest
+    }
+}
+
+/// How a compaction went, to compare compactors on the same disk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactionStats {
+    /// File blocks that ended up somewhere they were not before
+    pub blocks_moved: usize,
+    /// Fraction of the files that are split in more than one fragment
+    pub fragmentation_ratio: f64,
+    /// Blocks from the start of the disk to the end of the last file
+    pub used_extent: usize,
+}
+
+/// Files on a disk, sorted by location. A file may be split in several
+/// fragments, each of them represented as a File with the same id.
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Disk {
     pub files: Vec<File>,
 }
@@ -18,6 +277,174 @@ impl Disk {
             .map(|files| Gap(files[0].location.end..files[1].location.start))
             .collect()
     }
+
+    pub fn checksum(&self) -> usize {
+        self.files
+            .iter()
+            .map(|file| file.partial_checksum(&file.location))
+            .sum()
+    }
+
+    /// The disk after moving every file as the compactor decides
+    pub fn compacted<C: FileCompactor>(&self, compactor: &C) -> Disk {
+        let mut fragments: Vec<_> = compactor
+            .compact(self)
+            .into_iter()
+            .filter(|fragment| !fragment.location.is_empty())
+            .collect();
+        fragments.sort_by_key(|fragment| fragment.location.start);
+
+        // Stitch back together contiguous fragments of the same file
+        let mut files: Vec<File> = vec![];
+        for fragment in fragments {
+            match files.last_mut() {
+                Some(last)
+                    if last.id == fragment.id && last.location.end == fragment.location.start =>
+                {
+                    last.location.end = fragment.location.end;
+                }
+                _ => files.push(fragment),
+            }
+        }
+
+        Disk { files }
+    }
+
+    /// Compares this disk against a compacted version of it
+    pub fn compaction_stats(&self, compacted: &Disk) -> CompactionStats {
+        let original_blocks = self.blocks();
+        let blocks_moved = compacted
+            .blocks()
+            .into_iter()
+            .enumerate()
+            .filter(|&(idx, block)| {
+                block.is_some() && original_blocks.get(idx).copied().flatten() != block
+            })
+            .count();
+
+        let mut fragments: HashMap<usize, usize> = HashMap::new();
+        for file in compacted.files.iter() {
+            *fragments.entry(file.id).or_insert(0) += 1;
+        }
+        let fragmented = fragments.values().filter(|&&count| count > 1).count();
+        let fragmentation_ratio = if fragments.is_empty() {
+            0.0
+        } else {
+            fragmented as f64 / fragments.len() as f64
+        };
+
+        CompactionStats {
+            blocks_moved,
+            fragmentation_ratio,
+            used_extent: compacted
+                .files
+                .last()
+                .map(|file| file.location.end)
+                .unwrap_or(0),
+        }
+    }
+
+    /// File id stored in each block, up to the end of the last file
+    pub fn blocks(&self) -> Vec<Option<usize>> {
+        let length = self.files.last().map(|file| file.location.end).unwrap_or(0);
+        let mut blocks = vec![None; length];
+
+        for file in self.files.iter() {
+            for block in file.location.clone() {
+                blocks[block] = Some(file.id);
+            }
+        }
+
+        blocks
+    }
+
+    /// The layout as in the puzzle examples, e.g. 00...111...2...333.
+    /// Only the last digit of each file id is shown.
+    pub fn to_dense_string(&self) -> String {
+        self.blocks()
+            .into_iter()
+            .map(|block| match block {
+                Some(id) => char::from_digit((id % 10) as u32, 10).unwrap(),
+                None => '.',
+            })
+            .collect()
+    }
+
+    /// Serialises back to the compressed digit format. That format implies
+    /// file ids from their order and cannot hold runs longer than 9 blocks,
+    /// so this fails for disks where the ids are out of order, files are
+    /// fragmented, or there is a long gap, which is often the case after
+    /// compacting them.
+    pub fn to_digits(&self) -> Result<String, DigitsError> {
+        let mut digits = String::new();
+        let mut current_start = 0;
+
+        for (idx, file) in self.files.iter().enumerate() {
+            if file.id != idx {
+                return Err(DigitsError::OutOfOrder {
+                    position: idx,
+                    id: file.id,
+                });
+            }
+
+            let gap = file.location.start - current_start;
+            if idx > 0 {
+                digits.push(Disk::to_digit(gap).ok_or(DigitsError::GapTooLong {
+                    before: file.id,
+                    size: gap,
+                })?);
+            } else if gap != 0 {
+                return Err(DigitsError::LeadingGap { size: gap });
+            }
+
+            digits.push(Disk::to_digit(file.size()).ok_or(DigitsError::FileTooLong {
+                id: file.id,
+                size: file.size(),
+            })?);
+            current_start = file.location.end;
+        }
+
+        Ok(digits)
+    }
+
+    fn to_digit(size: usize) -> Option<char> {
+        u32::try_from(size)
+            .ok()
+            .and_then(|size| char::from_digit(size, 10))
+    }
+}
+
+/// Why a disk cannot be written in the compressed digit format
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DigitsError {
+    /// The file at this position in the disk does not have the id implied
+    /// by it, because files were moved past each other or fragmented
+    OutOfOrder { position: usize, id: usize },
+    /// The disk does not start with a file
+    LeadingGap { size: usize },
+    /// A file spans more blocks than a single digit can tell
+    FileTooLong { id: usize, size: usize },
+    /// The free space before a file is longer than a single digit can tell
+    GapTooLong { before: usize, size: usize },
+}
+
+impl fmt::Display for DigitsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DigitsError::OutOfOrder { position, id } => {
+                write!(f, "file {} is in position {}", id, position)
+            }
+            DigitsError::LeadingGap { size } => {
+                write!(f, "disk starts with {} free blocks", size)
+            }
+            DigitsError::FileTooLong { id, size } => {
+                write!(f, "file {} has {} blocks", id, size)
+            }
+            DigitsError::GapTooLong { before, size } => {
+                write!(f, "{} free blocks before file {}", size, before)
+            }
+        }
+    }
 }
 
 impl FromStr for Disk {
@@ -62,7 +489,7 @@ impl FromStr for Disk {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct File {
     id: usize,
     pub location: DiskRange,
@@ -70,7 +497,7 @@ pub struct File {
 
 impl File {
     pub fn partial_checksum(&self, range: &DiskRange) -> usize {
-        if range.len() == 0 {
+        if range.is_empty() {
             return 0;
         }
 
@@ -90,3 +517,497 @@ impl Gap {
         Self(self.0.start + space..self.0.end)
     }
 }
+
+/// Index over the gaps of a disk to find the leftmost one with enough room
+/// in logarithmic time. It is a segment tree over the gaps in disk order,
+/// where each node holds the largest gap size below it.
+pub struct FreeSpace {
+    gaps: Vec<Gap>,
+    leaves: usize,
+    largest: Vec<usize>,
+    by_size: BTreeMap<usize, BTreeSet<usize>>, // size -> indices of non-empty gaps
+}
+
+impl FreeSpace {
+    pub fn new(gaps: Vec<Gap>) -> Self {
+        let leaves = gaps.len().next_power_of_two();
+        let mut largest = vec![0; 2 * leaves];
+
+        for (idx, gap) in gaps.iter().enumerate() {
+            largest[leaves + idx] = gap.0.len();
+        }
+        for node in (1..leaves).rev() {
+            largest[node] = max(largest[2 * node], largest[2 * node + 1]);
+        }
+
+        let mut by_size: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        for (idx, gap) in gaps.iter().enumerate() {
+            if !gap.0.is_empty() {
+                by_size.entry(gap.0.len()).or_default().insert(idx);
+            }
+        }
+
+        Self {
+            gaps,
+            leaves,
+            largest,
+            by_size,
+        }
+    }
+
+    /// Index of the leftmost gap with at least the given size that starts
+    /// before the given position.
+    pub fn leftmost_fit(&self, size: usize, before: usize) -> Option<usize> {
+        if self.gaps.is_empty() || self.largest[1] < size {
+            return None;
+        }
+
+        let mut node = 1;
+        while node < self.leaves {
+            // Go left whenever possible, there is a fit on the right otherwise
+            node = if self.largest[2 * node] >= size {
+                2 * node
+            } else {
+                2 * node + 1
+            };
+        }
+
+        let idx = node - self.leaves;
+        Some(idx).filter(|&idx| self.gaps[idx].0.start < before)
+    }
+
+    /// Index of the smallest gap with at least the given size that starts
+    /// before the given position, the leftmost one among equals.
+    pub fn best_fit(&self, size: usize, before: usize) -> Option<usize> {
+        self.first_fit_by_size(self.by_size.range(max(size, 1)..), before)
+    }
+
+    /// Index of the largest gap with at least the given size that starts
+    /// before the given position, the leftmost one among equals.
+    pub fn worst_fit(&self, size: usize, before: usize) -> Option<usize> {
+        self.first_fit_by_size(self.by_size.range(max(size, 1)..).rev(), before)
+    }
+
+    fn first_fit_by_size<'a, I>(&self, buckets: I, before: usize) -> Option<usize>
+    where
+        I: Iterator<Item = (&'a usize, &'a BTreeSet<usize>)>,
+    {
+        // Gap indices follow the disk order, so the first one of each size is
+        // the leftmost, and if it is too far right so are all the others
+        buckets
+            .filter_map(|(_, indices)| indices.first().copied())
+            .find(|&idx| self.gaps[idx].0.start < before)
+    }
+
+    pub fn gap(&self, idx: usize) -> &Gap {
+        &self.gaps[idx]
+    }
+
+    /// Takes the given amount of space from the start of a gap
+    pub fn claim(&mut self, idx: usize, space: usize) -> DiskRange {
+        let gap = &mut self.gaps[idx];
+        let claimed = gap.0.start..gap.0.start + space;
+        let previous_size = gap.0.len();
+        *gap = gap.claim(space);
+
+        if let Some(indices) = self.by_size.get_mut(&previous_size) {
+            indices.remove(&idx);
+            if indices.is_empty() {
+                self.by_size.remove(&previous_size);
+            }
+        }
+        if !gap.0.is_empty() {
+            self.by_size.entry(gap.0.len()).or_default().insert(idx);
+        }
+
+        let mut node = self.leaves + idx;
+        self.largest[node] = gap.0.len();
+        while node > 1 {
+            node /= 2;
+            self.largest[node] = max(self.largest[2 * node], self.largest[2 * node + 1]);
+        }
+
+        claimed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Rng;
+    use std::collections::HashSet;
+
+    fn gaps(sizes: &[usize]) -> Vec<Gap> {
+        // One block wide files between the gaps
+        let mut start = 1;
+        sizes
+            .iter()
+            .map(|size| {
+                let gap = Gap(start..start + size);
+                start += size + 1;
+                gap
+            })
+            .collect()
+    }
+
+    fn random_disks(count: usize) -> Vec<String> {
+        let mut rng = Rng::new(42);
+
+        (0..count)
+            .map(|idx| {
+                (0..2 * (idx % 50) + 1)
+                    .map(|_| char::from_digit(rng.below(10) as u32, 10).unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dense_string() {
+        let disk: Disk = "2333133121414131402".parse().unwrap();
+
+        assert_eq!(
+            "00...111...2...333.44.5555.6666.777.888899",
+            disk.to_dense_string()
+        );
+        assert_eq!(
+            "0099811188827773336446555566",
+            disk.compacted(&BlockFileCompactor).to_dense_string()
+        );
+        assert_eq!(1928, disk.compacted(&BlockFileCompactor).checksum());
+    }
+
+    #[test]
+    fn test_digits_round_trip() {
+        // A lone empty file, a full digit file, and empty files between gaps
+        let edge_cases = ["0", "9", "90909", "0909090"].map(String::from);
+
+        for input in edge_cases.into_iter().chain(random_disks(200)) {
+            let disk: Disk = input.parse().unwrap();
+            let serialised = disk.to_digits().unwrap();
+            // Zero-size files do not show up in the blocks, so compare those
+            let reparsed: Disk = serialised.parse().unwrap();
+
+            assert_eq!(input, serialised);
+            assert_eq!(disk.blocks(), reparsed.blocks());
+        }
+    }
+
+    #[test]
+    fn test_digits_errors() {
+        let file = |id, location| File { id, location };
+        let disk: Disk = "12345".parse().unwrap();
+
+        // 022111222......
+        assert_eq!(
+            Err(DigitsError::OutOfOrder { position: 1, id: 2 }),
+            disk.compacted(&BlockFileCompactor).to_digits()
+        );
+        assert_eq!(
+            Err(DigitsError::LeadingGap { size: 2 }),
+            Disk {
+                files: vec![file(0, 2..3)]
+            }
+            .to_digits()
+        );
+        assert_eq!(
+            Err(DigitsError::FileTooLong { id: 0, size: 10 }),
+            Disk {
+                files: vec![file(0, 0..10)]
+            }
+            .to_digits()
+        );
+
+        let error = Disk {
+            files: vec![file(0, 0..1), file(1, 11..12)],
+        }
+        .to_digits()
+        .unwrap_err();
+        assert_eq!(
+            DigitsError::GapTooLong {
+                before: 1,
+                size: 10
+            },
+            error
+        );
+        assert_eq!("10 free blocks before file 1", error.to_string());
+    }
+
+    #[test]
+    fn test_compacted_keeps_files() {
+        for input in random_disks(200) {
+            let disk: Disk = input.parse().unwrap();
+            let compacted = disk.compacted(&BlockFileCompactor);
+            let count_blocks = |disk: &Disk| {
+                let mut counts = std::collections::HashMap::new();
+                for id in disk.blocks().into_iter().flatten() {
+                    *counts.entry(id).or_insert(0) += 1;
+                }
+                counts
+            };
+
+            assert_eq!(count_blocks(&disk), count_blocks(&compacted));
+            // There is nothing left to move, so compacting is idempotent
+            assert_eq!(compacted, compacted.compacted(&BlockFileCompactor));
+            assert!(compacted.blocks().into_iter().all(|block| block.is_some()));
+
+            for other in [
+                disk.compacted(&WholeFileCompactor),
+                disk.compacted(&BestFitCompactor),
+                disk.compacted(&WorstFitCompactor),
+                disk.compacted(&DefragmentCompactor),
+                disk.compacted(&RepeatedBestFitCompactor::default()),
+            ] {
+                assert_eq!(count_blocks(&disk), count_blocks(&other));
+                assert!(other
+                    .files
+                    .windows(2)
+                    .all(|pair| pair[0].location.end <= pair[1].location.start));
+            }
+        }
+    }
+
+    #[test]
+    fn test_compaction_stats() {
+        // 0....1..222..3
+        let disk: Disk = "1412321".parse().unwrap();
+        let check = |compacted: Disk, layout: &str, blocks_moved, used_extent| {
+            let stats = disk.compaction_stats(&compacted);
+
+            assert_eq!(layout, compacted.to_dense_string());
+            assert_eq!(blocks_moved, stats.blocks_moved);
+            assert_eq!(used_extent, stats.used_extent);
+            assert_eq!(0.0, stats.fragmentation_ratio);
+        };
+
+        check(disk.compacted(&BlockFileCompactor), "032221", 4, 6);
+        check(disk.compacted(&WholeFileCompactor), "032221", 4, 6);
+        check(disk.compacted(&WorstFitCompactor), "032221", 4, 6);
+        // The 3 takes the small gap, so the 1 is moved out of its way
+        check(disk.compacted(&BestFitCompactor), "02221.3", 5, 7);
+        check(disk.compacted(&DefragmentCompactor), "012223", 5, 6);
+        // A second pass moves the 3 into the space the 1 left behind
+        check(
+            disk.compacted(&RepeatedBestFitCompactor::default()),
+            "022213",
+            5,
+            6,
+        );
+        check(
+            disk.compacted(&ShortestDiskCompactor::default()),
+            "012223",
+            5,
+            6,
+        );
+
+        // Moving blocks one by one splits the 3 in two fragments
+        let disk: Disk = "1131302".parse().unwrap();
+        let compacted = disk.compacted(&BlockFileCompactor);
+        assert_eq!("031113222", compacted.to_dense_string());
+        assert_eq!(0.25, disk.compaction_stats(&compacted).fragmentation_ratio);
+    }
+
+    /// Shortest extent reachable by moving whole files left into free
+    /// space one at a time, trying every sequence of moves
+    fn shortest_extent(disk: &Disk) -> usize {
+        let sizes: Vec<usize> = disk.files.iter().map(File::size).collect();
+        let starts: Vec<usize> = disk.files.iter().map(|file| file.location.start).collect();
+        let extent = |starts: &[usize]| {
+            (0..starts.len())
+                .filter(|&idx| sizes[idx] > 0)
+                .map(|idx| starts[idx] + sizes[idx])
+                .max()
+                .unwrap_or(0)
+        };
+
+        let mut best = extent(&starts);
+        let mut seen = HashSet::from([starts.clone()]);
+        let mut pending = vec![starts];
+
+        while let Some(starts) = pending.pop() {
+            best = best.min(extent(&starts));
+
+            for idx in (0..starts.len()).filter(|&idx| sizes[idx] > 0) {
+                for start in (0..starts[idx]).filter(|start| start + sizes[idx] <= starts[idx]) {
+                    let is_free = (0..starts.len()).all(|other| {
+                        other == idx
+                            || sizes[other] == 0
+                            || starts[other] + sizes[other] <= start
+                            || start + sizes[idx] <= starts[other]
+                    });
+                    let mut moved = starts.clone();
+                    moved[idx] = start;
+                    if is_free && seen.insert(moved.clone()) {
+                        pending.push(moved);
+                    }
+                }
+            }
+        }
+
+        best
+    }
+
+    #[test]
+    fn test_shortest_disk_against_brute_force() {
+        let shortest_compactor = ShortestDiskCompactor::default();
+        let best_fit_compactor = RepeatedBestFitCompactor::default();
+        let used_extent =
+            |disk: &Disk, compacted: Disk| disk.compaction_stats(&compacted).used_extent;
+        let file_sizes = |disk: &Disk| {
+            let mut sizes: Vec<(usize, usize)> = disk
+                .files
+                .iter()
+                .map(|file| (file.id, file.size()))
+                .filter(|&(_, size)| size > 0)
+                .collect();
+            sizes.sort();
+            sizes
+        };
+        let mut rng = Rng::new(33);
+
+        for _ in 0..300 {
+            let input: String = (0..2 * rng.below(3) + 3)
+                .map(|_| char::from_digit(rng.below(4) as u32, 10).unwrap())
+                .collect();
+            let disk: Disk = input.parse().unwrap();
+            let shortest = shortest_extent(&disk);
+
+            let compacted = disk.compacted(&shortest_compactor);
+            assert_eq!(shortest, used_extent(&disk, compacted.clone()), "{}", input);
+            assert_eq!(file_sizes(&disk), file_sizes(&compacted));
+            assert!(used_extent(&disk, disk.compacted(&best_fit_compactor)) >= shortest);
+        }
+
+        // File 2 takes the front gap first, which leaves file 1 nowhere to go,
+        // while moving file 1 there first makes room for file 2 behind it
+        let disk: Disk = "02201".parse().unwrap();
+        assert_eq!(3, shortest_extent(&disk));
+        assert_eq!(3, used_extent(&disk, disk.compacted(&shortest_compactor)));
+        assert_eq!(4, used_extent(&disk, disk.compacted(&best_fit_compactor)));
+        assert_eq!("112", disk.compacted(&shortest_compactor).to_dense_string());
+    }
+
+    #[test]
+    fn test_best_and_worst_fit() {
+        let mut free_space = FreeSpace::new(gaps(&[1, 0, 3, 2, 5, 3]));
+
+        assert_eq!(Some(3), free_space.best_fit(2, 100));
+        assert_eq!(Some(2), free_space.best_fit(3, 100));
+        assert_eq!(Some(4), free_space.worst_fit(1, 100));
+        assert_eq!(Some(2), free_space.worst_fit(1, 10));
+        assert_eq!(None, free_space.best_fit(4, 10));
+        assert_eq!(None, free_space.worst_fit(6, 100));
+
+        free_space.claim(4, 4);
+        assert_eq!(Some(0), free_space.best_fit(1, 100));
+        assert_eq!(Some(2), free_space.worst_fit(1, 100));
+    }
+
+    #[test]
+    fn test_leftmost_fit() {
+        let free_space = FreeSpace::new(gaps(&[1, 0, 3, 2, 5]));
+        let cases = [
+            (1, 100, Some(0)),
+            (2, 100, Some(2)),
+            (4, 100, Some(4)),
+            (6, 100, None),
+            (4, 10, None),
+            (0, 100, Some(0)),
+        ];
+
+        for (size, before, expected) in cases {
+            assert_eq!(expected, free_space.leftmost_fit(size, before));
+        }
+    }
+
+    #[test]
+    fn test_claim() {
+        let mut free_space = FreeSpace::new(gaps(&[1, 3, 2]));
+
+        assert_eq!(3..5, free_space.claim(1, 2));
+        assert_eq!(Some(2), free_space.leftmost_fit(2, 100));
+        assert_eq!(1..2, free_space.claim(0, 1));
+        assert_eq!(Some(1), free_space.leftmost_fit(1, 100));
+        assert_eq!(None, FreeSpace::new(vec![]).leftmost_fit(0, 100));
+    }
+
+    /// Compacts as the puzzle describes it, going through the gaps from the
+    /// left for every file
+    struct LinearScanCompactor {
+        whole_files: bool,
+    }
+
+    impl FileCompactor for LinearScanCompactor {
+        fn compact(&self, disk: &Disk) -> Vec<File> {
+            let mut gaps: Vec<DiskRange> = disk.gaps().into_iter().map(|gap| gap.0).collect();
+            let mut fragments = vec![];
+
+            for file in disk.files.iter().rev() {
+                let mut left = file.size();
+
+                for gap in gaps.iter_mut() {
+                    if left == 0 || gap.start >= file.location.start {
+                        break;
+                    }
+                    if gap.start == gap.end || (self.whole_files && gap.len() < left) {
+                        continue;
+                    }
+
+                    let size = min(left, gap.len());
+                    fragments.push(File {
+                        id: file.id,
+                        location: gap.start..gap.start + size,
+                    });
+                    gap.start += size;
+                    left -= size;
+                }
+
+                fragments.push(File {
+                    id: file.id,
+                    location: file.location.start..file.location.start + left,
+                });
+            }
+
+            fragments
+        }
+    }
+
+    #[test]
+    fn test_checksum_synthetic() {
+        // Files each followed by a gap that fits the one that comes a
+        // hundred files later
+        let synthetic = |files: usize| -> Disk {
+            let input: String = (0..files)
+                .map(|idx| format!("{}{}", 1 + idx % 9, 1 + (idx + 100) % 9))
+                .chain(["1".to_string()])
+                .collect();
+            input.parse().unwrap()
+        };
+
+        let disk = synthetic(3000);
+        for (compacted, expected) in [
+            (
+                disk.compacted(&BlockFileCompactor),
+                disk.compacted(&LinearScanCompactor { whole_files: false }),
+            ),
+            (
+                disk.compacted(&WholeFileCompactor),
+                disk.compacted(&LinearScanCompactor { whole_files: true }),
+            ),
+        ] {
+            assert_eq!(expected, compacted);
+            assert_eq!(expected.checksum(), compacted.checksum());
+        }
+
+        // The free space index keeps a million files fast
+        let disk = synthetic(1_000_000);
+        assert_eq!(
+            6249995208342250245,
+            disk.compacted(&BlockFileCompactor).checksum()
+        );
+        assert_eq!(
+            6249995208912083885,
+            disk.compacted(&WholeFileCompactor).checksum()
+        );
+    }
+}
@@ -177,6 +177,72 @@ where
     }
 }
 
+/// Disjoint-set forest over the nodes 0..size, with path compression and
+/// union by rank, so any sequence of operations runs in near-linear time.
+pub struct DisjointSet {
+    parents: Vec<usize>,
+    ranks: Vec<u8>,
+    sizes: Vec<usize>,
+}
+
+impl DisjointSet {
+    pub fn new(size: usize) -> Self {
+        Self {
+            parents: (0..size).collect(),
+            ranks: vec![0; size],
+            sizes: vec![1; size],
+        }
+    }
+
+    /// Returns the representative of the set the node belongs to
+    pub fn find(&mut self, node: usize) -> usize {
+        let mut root = node;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+
+        // Point everything along the way straight to the root
+        let mut current = node;
+        while self.parents[current] != root {
+            let next = self.parents[current];
+            self.parents[current] = root;
+            current = next;
+        }
+
+        root
+    }
+
+    /// Merges the sets of both nodes, returning whether they were disjoint
+    pub fn union(&mut self, first: usize, second: usize) -> bool {
+        let (mut root, mut child) = (self.find(first), self.find(second));
+        if root == child {
+            return false;
+        }
+
+        if self.ranks[root] < self.ranks[child] {
+            (root, child) = (child, root);
+        }
+        if self.ranks[root] == self.ranks[child] {
+            self.ranks[root] += 1;
+        }
+
+        self.parents[child] = root;
+        self.sizes[root] += self.sizes[child];
+
+        true
+    }
+
+    pub fn connected(&mut self, first: usize, second: usize) -> bool {
+        self.find(first) == self.find(second)
+    }
+
+    /// Number of nodes in the same set as the given one
+    pub fn size_of(&mut self, node: usize) -> usize {
+        let root = self.find(node);
+        self.sizes[root]
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -288,6 +354,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn disjoint_set() {
+        let mut sets = DisjointSet::new(6);
+
+        assert!(sets.union(0, 1));
+        assert!(sets.union(2, 3));
+        assert!(sets.union(1, 3));
+        assert!(!sets.union(0, 2));
+
+        assert!(sets.connected(0, 3));
+        assert!(!sets.connected(0, 4));
+        assert_eq!(4, sets.size_of(2));
+        assert_eq!(1, sets.size_of(5));
+    }
+
     struct Maze {
         grid: Grid,
         blocks: HashSet<Point2D<i8>>,
@@ -19,5 +19,9 @@ pub mod day7;
 pub mod day8;
 pub mod day9;
 pub mod graph;
+pub mod obstacles;
 pub mod parse;
 pub mod spatial;
+
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::spatial::{Direction, Point2D};
+
+/// Index over a set of obstacles that answers "which is the next obstacle in
+/// this direction" without walking the grid cell by cell. Obstacles are kept
+/// as sorted lists per row and per column, so queries are a binary search.
+#[derive(Clone, Debug, Default)]
+pub struct ObstacleIndex<T> {
+    rows: HashMap<T, Vec<T>>,    // y -> sorted x's
+    columns: HashMap<T, Vec<T>>, // x -> sorted y's
+}
+
+/// Anything that can tell the closest obstacle from a position in a direction
+pub trait NextObstacle<T> {
+    fn next_obstacle(&self, from: Point2D<T>, direction: Direction) -> Option<Point2D<T>>;
+}
+
+impl<T: Copy + Eq + Hash + Ord> ObstacleIndex<T> {
+    pub fn new() -> Self {
+        Self {
+            rows: HashMap::new(),
+            columns: HashMap::new(),
+        }
+    }
+
+    /// Adds an obstacle, returning whether it was not already present
+    pub fn insert(&mut self, obstacle: Point2D<T>) -> bool {
+        let row = self.rows.entry(obstacle.y).or_default();
+        match row.binary_search(&obstacle.x) {
+            Ok(_) => return false,
+            Err(idx) => row.insert(idx, obstacle.x),
+        }
+
+        let column = self.columns.entry(obstacle.x).or_default();
+        let idx = column.binary_search(&obstacle.y).unwrap_err();
+        column.insert(idx, obstacle.y);
+
+        true
+    }
+
+    /// Removes an obstacle, returning whether it was present
+    pub fn remove(&mut self, obstacle: &Point2D<T>) -> bool {
+        let Some(row) = self.rows.get_mut(&obstacle.y) else {
+            return false;
+        };
+        let Ok(idx) = row.binary_search(&obstacle.x) else {
+            return false;
+        };
+        row.remove(idx);
+
+        let column = self.columns.get_mut(&obstacle.x).unwrap();
+        let idx = column.binary_search(&obstacle.y).unwrap();
+        column.remove(idx);
+
+        true
+    }
+
+    pub fn contains(&self, position: &Point2D<T>) -> bool {
+        self.rows
+            .get(&position.y)
+            .map(|row| row.binary_search(&position.x).is_ok())
+            .unwrap_or(false)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.values().all(Vec::is_empty)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Point2D<T>> + '_ {
+        self.rows
+            .iter()
+            .flat_map(|(&y, row)| row.iter().map(move |&x| Point2D::new(x, y)))
+    }
+
+    /// A view of this index with some temporary obstacles on top, which
+    /// avoids cloning the whole index to try out a few extra obstacles.
+    pub fn with_extra<'a>(&'a self, extra: &'a [Point2D<T>]) -> ExtendedObstacleIndex<'a, T> {
+        ExtendedObstacleIndex { base: self, extra }
+    }
+}
+
+impl<T: Copy + Eq + Hash + Ord> NextObstacle<T> for ObstacleIndex<T> {
+    /// The closest obstacle strictly ahead of the given position. Up is
+    /// towards increasing y, as in `Point2D::advance`.
+    fn next_obstacle(&self, from: Point2D<T>, direction: Direction) -> Option<Point2D<T>> {
+        match direction {
+            Direction::Up | Direction::Down => {
+                let column = self.columns.get(&from.x)?;
+                let y = closest_ahead(column, from.y, direction == Direction::Up)?;
+                Some(Point2D::new(from.x, y))
+            }
+            Direction::Left | Direction::Right => {
+                let row = self.rows.get(&from.y)?;
+                let x = closest_ahead(row, from.x, direction == Direction::Right)?;
+                Some(Point2D::new(x, from.y))
+            }
+        }
+    }
+}
+
+/// Closest value in a sorted list strictly after (or before, if not forwards)
+/// the given one.
+fn closest_ahead<T: Copy + Ord>(sorted: &[T], from: T, forwards: bool) -> Option<T> {
+    if forwards {
+        sorted.get(sorted.partition_point(|&x| x <= from)).copied()
+    } else {
+        let idx = sorted.partition_point(|&x| x < from);
+        idx.checked_sub(1).map(|idx| sorted[idx])
+    }
+}
+
+impl<T: Copy + Eq + Hash + Ord> FromIterator<Point2D<T>> for ObstacleIndex<T> {
+    fn from_iter<I: IntoIterator<Item = Point2D<T>>>(obstacles: I) -> Self {
+        let mut index = Self::new();
+
+        for obstacle in obstacles {
+            index.insert(obstacle);
+        }
+
+        index
+    }
+}
+
+impl<T: Copy + Eq + Hash + Ord> IntoIterator for ObstacleIndex<T> {
+    type Item = Point2D<T>;
+    type IntoIter = std::vec::IntoIter<Point2D<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter().collect::<Vec<_>>().into_iter()
+    }
+}
+
+/// An ObstacleIndex with a few additional obstacles placed on top of it
+#[derive(Clone, Copy)]
+pub struct ExtendedObstacleIndex<'a, T> {
+    base: &'a ObstacleIndex<T>,
+    extra: &'a [Point2D<T>],
+}
+
+impl<T: Copy + Eq + Hash + Ord> ExtendedObstacleIndex<'_, T> {
+    pub fn contains(&self, position: &Point2D<T>) -> bool {
+        self.extra.contains(position) || self.base.contains(position)
+    }
+}
+
+impl<T: Copy + Eq + Hash + Ord> NextObstacle<T> for ExtendedObstacleIndex<'_, T> {
+    fn next_obstacle(&self, from: Point2D<T>, direction: Direction) -> Option<Point2D<T>> {
+        let ahead = |obstacle: &Point2D<T>| match direction {
+            Direction::Up => obstacle.x == from.x && obstacle.y > from.y,
+            Direction::Down => obstacle.x == from.x && obstacle.y < from.y,
+            Direction::Right => obstacle.y == from.y && obstacle.x > from.x,
+            Direction::Left => obstacle.y == from.y && obstacle.x < from.x,
+        };
+
+        // Only the y's vary going up or down, and only the x's going left or
+        // right, so the closest obstacle is the min or max of the candidates
+        let candidates = self
+            .base
+            .next_obstacle(from, direction)
+            .into_iter()
+            .chain(self.extra.iter().copied().filter(ahead));
+
+        match direction {
+            Direction::Up | Direction::Right => candidates.min(),
+            Direction::Down | Direction::Left => candidates.max(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_index() -> ObstacleIndex<i16> {
+        // ..#..
+        // #....
+        // ....#
+        // .#...
+        // (origin at the bottom left)
+        [
+            Point2D::new(2, 3),
+            Point2D::new(0, 2),
+            Point2D::new(4, 1),
+            Point2D::new(1, 0),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_next_obstacle() {
+        let index = sample_index();
+        let cases = [
+            (Point2D::new(2, 0), Direction::Up, Some(Point2D::new(2, 3))),
+            (Point2D::new(2, 3), Direction::Up, None),
+            (
+                Point2D::new(1, 3),
+                Direction::Down,
+                Some(Point2D::new(1, 0)),
+            ),
+            (Point2D::new(1, 0), Direction::Down, None),
+            (
+                Point2D::new(0, 1),
+                Direction::Right,
+                Some(Point2D::new(4, 1)),
+            ),
+            (
+                Point2D::new(4, 2),
+                Direction::Left,
+                Some(Point2D::new(0, 2)),
+            ),
+            (Point2D::new(3, 3), Direction::Right, None),
+        ];
+
+        for (from, direction, expected) in cases {
+            assert_eq!(
+                expected,
+                index.next_obstacle(from, direction),
+                "{:?} {:?}",
+                from,
+                direction
+            );
+        }
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut index = sample_index();
+        assert_eq!(4, index.len());
+        assert!(!index.insert(Point2D::new(2, 3)));
+        assert!(index.insert(Point2D::new(2, 1)));
+        assert_eq!(
+            Some(Point2D::new(2, 1)),
+            index.next_obstacle(Point2D::new(2, 0), Direction::Up)
+        );
+
+        assert!(index.remove(&Point2D::new(2, 1)));
+        assert!(!index.remove(&Point2D::new(2, 1)));
+        assert!(!index.contains(&Point2D::new(2, 1)));
+        assert_eq!(
+            Some(Point2D::new(2, 3)),
+            index.next_obstacle(Point2D::new(2, 0), Direction::Up)
+        );
+    }
+
+    #[test]
+    fn test_extra_obstacles() {
+        let index = sample_index();
+        let extra = [Point2D::new(2, 2), Point2D::new(3, 1)];
+        let extended = index.with_extra(&extra);
+
+        assert_eq!(
+            Some(Point2D::new(2, 2)),
+            extended.next_obstacle(Point2D::new(2, 0), Direction::Up)
+        );
+        assert_eq!(
+            Some(Point2D::new(2, 3)),
+            extended.next_obstacle(Point2D::new(2, 2), Direction::Up)
+        );
+        assert_eq!(
+            Some(Point2D::new(3, 1)),
+            extended.next_obstacle(Point2D::new(0, 1), Direction::Right)
+        );
+        assert_eq!(
+            Some(Point2D::new(4, 1)),
+            index.next_obstacle(Point2D::new(0, 1), Direction::Right)
+        );
+    }
+}
@@ -1,5 +1,5 @@
 use std::cmp::{max, min};
-use std::ops::{Add, Mul, Neg, Sub};
+use std::ops::{Add, Mul, Neg, RangeInclusive, Sub};
 use std::panic::catch_unwind;
 
 #[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
@@ -166,6 +166,45 @@ where
     }
 }
 
+/// A rectangle of points, bounds included
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rectangle<T> {
+    pub min: Point2D<T>,
+    pub max: Point2D<T>,
+}
+
+impl<T: Copy> Rectangle<T> {
+    /// The same rectangle with the given margin added on every side
+    pub fn grown(&self, margin: T) -> Self
+    where
+        T: Add<Output = T> + Sub<Output = T>,
+    {
+        Self {
+            min: self.min - Point2D::new(margin, margin),
+            max: self.max + Point2D::new(margin, margin),
+        }
+    }
+
+    pub fn contains(&self, point: &Point2D<T>) -> bool
+    where
+        T: PartialOrd,
+    {
+        point.x >= self.min.x
+            && point.x <= self.max.x
+            && point.y >= self.min.y
+            && point.y <= self.max.y
+    }
+
+    /// Every point inside, row by row
+    pub fn points(&self) -> impl Iterator<Item = Point2D<T>> + '_
+    where
+        RangeInclusive<T>: Iterator<Item = T>,
+    {
+        (self.min.y..=self.max.y)
+            .flat_map(|y| (self.min.x..=self.max.x).map(move |x| Point2D::new(x, y)))
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum Direction {
     Up,
@@ -300,4 +339,33 @@ mod tests {
 
         assert_elements_match(expected, l1_ball);
     }
+
+    #[test]
+    fn test_rectangle() {
+        let rectangle = Rectangle {
+            min: Point2D::new(1, -1),
+            max: Point2D::new(2, 0),
+        };
+        assert_eq!(
+            vec![
+                Point2D::new(1, -1),
+                Point2D::new(2, -1),
+                Point2D::new(1, 0),
+                Point2D::new(2, 0),
+            ],
+            rectangle.points().collect::<Vec<_>>()
+        );
+
+        let grown = rectangle.grown(1);
+        assert_eq!(16, grown.points().count());
+        assert!(grown.points().all(|point| grown.contains(&point)));
+        assert_eq!(
+            12,
+            grown
+                .points()
+                .filter(|point| !rectangle.contains(point))
+                .count()
+        );
+        assert!(!grown.contains(&Point2D::new(4, 0)));
+    }
 }
@@ -0,0 +1,21 @@
+/// Seeded linear congruential generator, so randomised tests are repeatable
+/// without pulling in a dependency
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A number in 0..bound
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.state = self
+            .state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+
+        (self.state >> 33) % bound
+    }
+}